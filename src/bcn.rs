@@ -0,0 +1,830 @@
+//! CPU block compression for the formats used by `--convert`, and for re-encoding the mips of
+//! textures that were loaded compressed.
+//!
+//! These encoders aim to be simple and predictable rather than optimal. BC7 only uses
+//! mode 6 (single subset, RGBA, 4 bit indices), which is good enough for the Sponza textures.
+//! BC6H only uses mode 11 (single region, 10 bit endpoints, 4 bit indices).
+
+use half::f16;
+use image::{DynamicImage, RgbaImage};
+
+use crate::bcn_decode::bc6h_unquantize;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BlockFormat {
+    /// Single channel, taken from the red channel.
+    Bc4,
+    /// Two channels, taken from the red and green channels.
+    Bc5,
+    Bc7,
+}
+
+impl BlockFormat {
+    pub fn block_bytes(self) -> usize {
+        match self {
+            BlockFormat::Bc4 => 8,
+            BlockFormat::Bc5 | BlockFormat::Bc7 => 16,
+        }
+    }
+}
+
+impl std::str::FromStr for BlockFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bc4" => Ok(BlockFormat::Bc4),
+            "bc5" => Ok(BlockFormat::Bc5),
+            "bc7" => Ok(BlockFormat::Bc7),
+            _ => Err(anyhow::anyhow!(
+                "Unknown block format {s}, expected bc4, bc5 or bc7"
+            )),
+        }
+    }
+}
+
+/// Every BCn format Bevy can load, so textures loaded compressed can be decoded and re-encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BcnFormat {
+    /// Color with 1 bit alpha.
+    Bc1,
+    /// Color with explicit 4 bit alpha.
+    Bc2,
+    /// Color with interpolated alpha.
+    Bc3,
+    Bc4 {
+        signed: bool,
+    },
+    Bc5 {
+        signed: bool,
+    },
+    /// HDR color, decoded to and encoded from floats.
+    Bc6h {
+        signed: bool,
+    },
+    Bc7,
+}
+
+impl BcnFormat {
+    pub fn block_bytes(self) -> usize {
+        match self {
+            BcnFormat::Bc1 | BcnFormat::Bc4 { .. } => 8,
+            _ => 16,
+        }
+    }
+}
+
+/// Compresses a whole image, row of blocks by row of blocks.
+/// Images that aren't a multiple of 4 are padded by clamping to the edge.
+pub fn compress(image: &RgbaImage, format: BlockFormat) -> Vec<u8> {
+    compress_blocks(
+        image.dimensions(),
+        format.block_bytes(),
+        |x, y| image.get_pixel(x, y).0,
+        |texels, out| match format {
+            BlockFormat::Bc4 => out.extend_from_slice(&encode_bc4_block(&channel(texels, 0))),
+            BlockFormat::Bc5 => out.extend_from_slice(&encode_bc5_block(texels)),
+            BlockFormat::Bc7 => out.extend_from_slice(&encode_bc7_block(texels)),
+        },
+    )
+}
+
+/// Like [`compress`], for any of the formats Bevy loads.
+pub fn compress_bcn(image: &DynamicImage, format: BcnFormat) -> Vec<u8> {
+    let dimensions = (image.width(), image.height());
+    let block_bytes = format.block_bytes();
+    if let BcnFormat::Bc6h { signed } = format {
+        let image = image.to_rgba32f();
+        return compress_blocks(
+            dimensions,
+            block_bytes,
+            |x, y| image.get_pixel(x, y).0,
+            |texels, out| {
+                let texels = texels.map(|[r, g, b, _]| [r, g, b]);
+                out.extend_from_slice(&encode_bc6h_block(&texels, signed));
+            },
+        );
+    }
+    let image = image.to_rgba8();
+    compress_blocks(
+        dimensions,
+        block_bytes,
+        |x, y| image.get_pixel(x, y).0,
+        |texels, out| match format {
+            BcnFormat::Bc1 => out.extend_from_slice(&encode_bc1_block(texels, true)),
+            BcnFormat::Bc2 => out.extend_from_slice(&encode_bc2_block(texels)),
+            BcnFormat::Bc3 => out.extend_from_slice(&encode_bc3_block(texels)),
+            BcnFormat::Bc4 { signed } => {
+                out.extend_from_slice(&encode_bc4_block_as(&channel(texels, 0), signed))
+            }
+            BcnFormat::Bc5 { signed } => {
+                out.extend_from_slice(&encode_bc4_block_as(&channel(texels, 0), signed));
+                out.extend_from_slice(&encode_bc4_block_as(&channel(texels, 1), signed));
+            }
+            BcnFormat::Bc7 => out.extend_from_slice(&encode_bc7_block(texels)),
+            BcnFormat::Bc6h { .. } => unreachable!(),
+        },
+    )
+}
+
+fn compress_blocks<T: Copy + Default>(
+    (width, height): (u32, u32),
+    block_bytes: usize,
+    pixel: impl Fn(u32, u32) -> [T; 4],
+    mut encode: impl FnMut(&[[T; 4]; 16], &mut Vec<u8>),
+) -> Vec<u8> {
+    let blocks_x = width.div_ceil(4);
+    let blocks_y = height.div_ceil(4);
+    let mut out = Vec::with_capacity((blocks_x * blocks_y) as usize * block_bytes);
+
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut texels = [[T::default(); 4]; 16];
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let x = (bx * 4 + i as u32 % 4).min(width - 1);
+                let y = (by * 4 + i as u32 / 4).min(height - 1);
+                *texel = pixel(x, y);
+            }
+            encode(&texels, &mut out);
+        }
+    }
+
+    out
+}
+
+fn channel(texels: &[[u8; 4]; 16], c: usize) -> [u8; 16] {
+    let mut values = [0u8; 16];
+    for (value, texel) in values.iter_mut().zip(texels) {
+        *value = texel[c];
+    }
+    values
+}
+
+/// Returns the 8 entry BC4 palette for the given endpoints.
+pub fn bc4_palette(r0: u8, r1: u8) -> [u8; 8] {
+    let (a, b) = (r0 as u32, r1 as u32);
+    if r0 > r1 {
+        [
+            r0,
+            r1,
+            ((6 * a + b) / 7) as u8,
+            ((5 * a + 2 * b) / 7) as u8,
+            ((4 * a + 3 * b) / 7) as u8,
+            ((3 * a + 4 * b) / 7) as u8,
+            ((2 * a + 5 * b) / 7) as u8,
+            ((a + 6 * b) / 7) as u8,
+        ]
+    } else {
+        [
+            r0,
+            r1,
+            ((4 * a + b) / 5) as u8,
+            ((3 * a + 2 * b) / 5) as u8,
+            ((2 * a + 3 * b) / 5) as u8,
+            ((a + 4 * b) / 5) as u8,
+            0,
+            255,
+        ]
+    }
+}
+
+pub fn encode_bc4_block(values: &[u8; 16]) -> [u8; 8] {
+    let max = *values.iter().max().unwrap();
+    let min = *values.iter().min().unwrap();
+
+    let mut block = [0u8; 8];
+    block[0] = max;
+    block[1] = min;
+    if max == min {
+        return block;
+    }
+
+    let palette = bc4_palette(max, min);
+    let mut indices = 0u64;
+    for (i, &value) in values.iter().enumerate() {
+        let index = (0..8)
+            .min_by_key(|&p| (palette[p] as i32 - value as i32).abs())
+            .unwrap() as u64;
+        indices |= index << (3 * i);
+    }
+    block[2..].copy_from_slice(&indices.to_le_bytes()[..6]);
+    block
+}
+
+pub fn encode_bc5_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&encode_bc4_block(&channel(texels, 0)));
+    block[8..].copy_from_slice(&encode_bc4_block(&channel(texels, 1)));
+    block
+}
+
+/// Signed blocks take values offset by 128 like [`crate::bcn_decode::decode_bc4_block`] returns.
+pub fn encode_bc4_block_as(values: &[u8; 16], signed: bool) -> [u8; 8] {
+    if !signed {
+        return encode_bc4_block(values);
+    }
+    // -128 isn't a valid value, it decodes as -1.0 like -127
+    let mut block = encode_bc4_block(&values.map(|value| value.max(1)));
+    for endpoint in &mut block[..2] {
+        *endpoint = endpoint.wrapping_sub(128);
+    }
+    block
+}
+
+fn to_rgb565(color: [f32; 3]) -> u16 {
+    let r = (color[0] * 31.0 / 255.0).round().clamp(0.0, 31.0) as u16;
+    let g = (color[1] * 63.0 / 255.0).round().clamp(0.0, 63.0) as u16;
+    let b = (color[2] * 31.0 / 255.0).round().clamp(0.0, 31.0) as u16;
+    r << 11 | g << 5 | b
+}
+
+fn from_rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) as u8 & 0x1f;
+    let g = (color >> 5) as u8 & 0x3f;
+    let b = color as u8 & 0x1f;
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+}
+
+/// Returns the 4 entry BC1 palette for the given endpoints. With `punchthrough`, endpoints that
+/// aren't in decreasing order give 3 colors and transparent black, BC2 and BC3 always use 4 colors.
+pub fn bc1_palette(c0: u16, c1: u16, punchthrough: bool) -> [[u8; 4]; 4] {
+    let (a, b) = (from_rgb565(c0), from_rgb565(c1));
+    let mix = |wa: u32, wb: u32| {
+        let [r, g, b] =
+            [0, 1, 2].map(|c| ((wa * a[c] as u32 + wb * b[c] as u32) / (wa + wb)) as u8);
+        [r, g, b, 255]
+    };
+    let (a, b) = ([a[0], a[1], a[2], 255], [b[0], b[1], b[2], 255]);
+    if c0 > c1 || !punchthrough {
+        [a, b, mix(2, 1), mix(1, 2)]
+    } else {
+        [a, b, mix(1, 1), [0, 0, 0, 0]]
+    }
+}
+
+/// With `punchthrough`, texels with an alpha under 128 become transparent black.
+pub fn encode_bc1_block(texels: &[[u8; 4]; 16], punchthrough: bool) -> [u8; 8] {
+    let transparent = |texel: &[u8; 4]| punchthrough && texel[3] < 128;
+    let has_transparent = texels.iter().any(transparent);
+    let pixels: Vec<[f32; 3]> = texels
+        .iter()
+        .filter(|texel| !transparent(texel))
+        .map(|texel| [0, 1, 2].map(|c| texel[c] as f32))
+        .collect();
+
+    let (e0, e1) = principal_endpoints(&pixels);
+    let (mut c0, mut c1) = (to_rgb565(e0), to_rgb565(e1));
+    // The order of the endpoints picks between 4 colors and 3 colors with transparency
+    if (c0 < c1) != has_transparent {
+        std::mem::swap(&mut c0, &mut c1);
+    }
+    let palette = bc1_palette(c0, c1, punchthrough);
+    let opaque_entries = if has_transparent || (punchthrough && c0 == c1) {
+        3
+    } else {
+        4
+    };
+
+    let mut indices = 0u32;
+    for (i, texel) in texels.iter().enumerate() {
+        let index = if transparent(texel) {
+            3
+        } else {
+            (0..opaque_entries)
+                .min_by_key(|&p| {
+                    (0..3)
+                        .map(|c| (palette[p][c] as i32 - texel[c] as i32).pow(2))
+                        .sum::<i32>()
+                })
+                .unwrap()
+        };
+        indices |= (index as u32) << (2 * i);
+    }
+
+    let mut block = [0u8; 8];
+    block[..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+pub fn encode_bc2_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut alpha = 0u64;
+    for (i, texel) in texels.iter().enumerate() {
+        alpha |= (((texel[3] as u64 * 15) + 127) / 255) << (4 * i);
+    }
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&alpha.to_le_bytes());
+    block[8..].copy_from_slice(&encode_bc1_block(texels, false));
+    block
+}
+
+pub fn encode_bc3_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let mut block = [0u8; 16];
+    block[..8].copy_from_slice(&encode_bc4_block(&channel(texels, 3)));
+    block[8..].copy_from_slice(&encode_bc1_block(texels, false));
+    block
+}
+
+pub const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+fn bc7_interpolate(e0: u8, e1: u8, weight: u32) -> u8 {
+    (((64 - weight) * e0 as u32 + weight * e1 as u32 + 32) >> 6) as u8
+}
+
+/// Quantizes an endpoint to 7 bits per channel plus a shared p-bit.
+/// Returns the 7 bit values and the p-bit that gives the lowest error.
+fn bc7_quantize_mode6(endpoint: [f32; 4]) -> ([u8; 4], u8) {
+    let mut best = ([0u8; 4], 0u8);
+    let mut best_error = f32::MAX;
+    for p in 0..2u8 {
+        let mut quantized = [0u8; 4];
+        let mut error = 0.0;
+        for c in 0..4 {
+            let q = ((endpoint[c] - p as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+            let value = (q << 1 | p) as f32;
+            error += (value - endpoint[c]).powi(2);
+            quantized[c] = q;
+        }
+        if error < best_error {
+            best_error = error;
+            best = (quantized, p);
+        }
+    }
+    best
+}
+
+struct Bc7Mode6 {
+    endpoints: [[u8; 4]; 2],
+    p_bits: [u8; 2],
+    indices: [u8; 16],
+    error: u32,
+}
+
+impl Bc7Mode6 {
+    fn expanded(&self, e: usize) -> [u8; 4] {
+        self.endpoints[e].map(|c| c << 1 | self.p_bits[e])
+    }
+
+    /// Picks the closest palette entry for every texel.
+    fn from_endpoints(texels: &[[u8; 4]; 16], e0: [f32; 4], e1: [f32; 4]) -> Self {
+        let (q0, p0) = bc7_quantize_mode6(e0);
+        let (q1, p1) = bc7_quantize_mode6(e1);
+        let mut result = Bc7Mode6 {
+            endpoints: [q0, q1],
+            p_bits: [p0, p1],
+            indices: [0; 16],
+            error: 0,
+        };
+
+        let (a, b) = (result.expanded(0), result.expanded(1));
+        let palette: Vec<[u8; 4]> = BC7_WEIGHTS_4
+            .iter()
+            .map(|&w| [0, 1, 2, 3].map(|c| bc7_interpolate(a[c], b[c], w)))
+            .collect();
+
+        for (i, texel) in texels.iter().enumerate() {
+            let (index, error) = palette
+                .iter()
+                .enumerate()
+                .map(|(p, entry)| {
+                    let error: u32 = (0..4)
+                        .map(|c| (entry[c] as i32 - texel[c] as i32).pow(2) as u32)
+                        .sum();
+                    (p, error)
+                })
+                .min_by_key(|(_, error)| *error)
+                .unwrap();
+            result.indices[i] = index as u8;
+            result.error += error;
+        }
+        result
+    }
+
+    /// Least squares fit of the endpoints for the current indices.
+    fn refit(&self, texels: &[[u8; 4]; 16]) -> Option<([f32; 4], [f32; 4])> {
+        let (mut aa, mut ab, mut bb) = (0.0f32, 0.0f32, 0.0f32);
+        let mut ax = [0.0f32; 4];
+        let mut bx = [0.0f32; 4];
+        for (texel, &index) in texels.iter().zip(&self.indices) {
+            let w = BC7_WEIGHTS_4[index as usize] as f32 / 64.0;
+            let (a, b) = (1.0 - w, w);
+            aa += a * a;
+            ab += a * b;
+            bb += b * b;
+            for c in 0..4 {
+                ax[c] += a * texel[c] as f32;
+                bx[c] += b * texel[c] as f32;
+            }
+        }
+        let det = aa * bb - ab * ab;
+        if det.abs() < f32::EPSILON {
+            return None;
+        }
+        let mut e0 = [0.0f32; 4];
+        let mut e1 = [0.0f32; 4];
+        for c in 0..4 {
+            e0[c] = ((bb * ax[c] - ab * bx[c]) / det).clamp(0.0, 255.0);
+            e1[c] = ((aa * bx[c] - ab * ax[c]) / det).clamp(0.0, 255.0);
+        }
+        Some((e0, e1))
+    }
+
+    fn pack(mut self) -> [u8; 16] {
+        // The anchor index is stored with an implicit 0 msb, so swap the endpoints if needed
+        if self.indices[0] & 0b1000 != 0 {
+            self.endpoints.swap(0, 1);
+            self.p_bits.swap(0, 1);
+            for index in self.indices.iter_mut() {
+                *index = 15 - *index;
+            }
+        }
+
+        let mut bits = 1u128 << 6;
+        let mut offset = 7;
+        for c in 0..4 {
+            for endpoint in &self.endpoints {
+                bits |= (endpoint[c] as u128) << offset;
+                offset += 7;
+            }
+        }
+        for p in self.p_bits {
+            bits |= (p as u128) << offset;
+            offset += 1;
+        }
+        for (i, &index) in self.indices.iter().enumerate() {
+            bits |= (index as u128) << offset;
+            offset += if i == 0 { 3 } else { 4 };
+        }
+        debug_assert_eq!(offset, 128);
+        bits.to_le_bytes()
+    }
+}
+
+pub fn encode_bc7_block(texels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let pixels: Vec<[f32; 4]> = texels.iter().map(|t| t.map(|c| c as f32)).collect();
+    let (e0, e1) = principal_endpoints(&pixels);
+    let e0 = e0.map(|c| c.clamp(0.0, 255.0));
+    let e1 = e1.map(|c| c.clamp(0.0, 255.0));
+
+    let mut best = Bc7Mode6::from_endpoints(texels, e0, e1);
+    for _ in 0..2 {
+        if best.error == 0 {
+            break;
+        }
+        let Some((e0, e1)) = best.refit(texels) else {
+            break;
+        };
+        let refined = Bc7Mode6::from_endpoints(texels, e0, e1);
+        if refined.error >= best.error {
+            break;
+        }
+        best = refined;
+    }
+
+    best.pack()
+}
+
+/// Value in the 16 bit range BC6H interpolates in that finishes unquantizing to this float.
+fn bc6h_target(value: f32, signed: bool) -> i32 {
+    let value = if value.is_nan() { 0.0 } else { value };
+    let bits = f16::from_f32(if signed { value } else { value.max(0.0) }).to_bits();
+    // Clamp infinities to the largest finite half
+    let magnitude = (bits & 0x7fff).min(0x7bff) as i32;
+    if !signed {
+        (magnitude * 64 + 30) / 31
+    } else if bits & 0x8000 != 0 {
+        -((magnitude * 32 + 30) / 31)
+    } else {
+        (magnitude * 32 + 30) / 31
+    }
+}
+
+/// Quantizes to the 10 bit endpoint whose unquantized value is closest.
+fn bc6h_quantize(target: f32, signed: bool) -> i32 {
+    let (min, max) = if signed { (-511, 511) } else { (0, 1023) };
+    let guess = ((target - 32.0 * target.signum()) / 64.0).round() as i32;
+    (guess - 1..=guess + 1)
+        .map(|q| q.clamp(min, max))
+        .min_by_key(|&q| (bc6h_unquantize(q, 10, signed) - target as i32).abs())
+        .unwrap()
+}
+
+pub fn encode_bc6h_block(texels: &[[f32; 3]; 16], signed: bool) -> [u8; 16] {
+    let targets = texels.map(|texel| texel.map(|c| bc6h_target(c, signed)));
+    let pixels: Vec<[f32; 3]> = targets.iter().map(|t| t.map(|c| c as f32)).collect();
+    let (e0, e1) = principal_endpoints(&pixels);
+    let mut endpoints = [e0, e1].map(|e| e.map(|c| bc6h_quantize(c, signed)));
+    let unquantized = endpoints.map(|e| e.map(|c| bc6h_unquantize(c, 10, signed)));
+
+    let mut indices = targets.map(|target| {
+        (0..16)
+            .min_by_key(|&index| {
+                let weight = BC7_WEIGHTS_4[index] as i64;
+                (0..3)
+                    .map(|c| {
+                        let (a, b) = (unquantized[0][c] as i64, unquantized[1][c] as i64);
+                        let value = ((64 - weight) * a + weight * b + 32) >> 6;
+                        (value - target[c] as i64).pow(2)
+                    })
+                    .sum::<i64>()
+            })
+            .unwrap() as u128
+    });
+    // The first index is stored with an implicit 0 msb, so swap the endpoints if needed
+    if indices[0] & 0b1000 != 0 {
+        endpoints.swap(0, 1);
+        indices = indices.map(|index| 15 - index);
+    }
+
+    // Mode 11 is stored as 0b00011
+    let mut bits = 0b00011u128;
+    let mut offset = 5;
+    for endpoint in endpoints {
+        for c in endpoint {
+            bits |= (c as u128 & 0x3ff) << offset;
+            offset += 10;
+        }
+    }
+    for (i, index) in indices.into_iter().enumerate() {
+        bits |= index << offset;
+        offset += if i == 0 { 3 } else { 4 };
+    }
+    debug_assert_eq!(offset, 128);
+    bits.to_le_bytes()
+}
+
+/// Ends of the principal axis through `pixels`, using a few power iterations on the covariance
+/// matrix. The endpoints aren't clamped.
+fn principal_endpoints<const N: usize>(pixels: &[[f32; N]]) -> ([f32; N], [f32; N]) {
+    let count = pixels.len().max(1) as f32;
+    let mut mean = [0.0f32; N];
+    for pixel in pixels {
+        for c in 0..N {
+            mean[c] += pixel[c] / count;
+        }
+    }
+
+    let mut covariance = [[0.0f32; N]; N];
+    for pixel in pixels {
+        let d: [f32; N] = std::array::from_fn(|c| pixel[c] - mean[c]);
+        for i in 0..N {
+            for j in 0..N {
+                covariance[i][j] += d[i] * d[j];
+            }
+        }
+    }
+    // Start from the covariance of the channel that varies the most, a fixed start like the
+    // gray axis can be orthogonal to the principal axis and never find it
+    let widest = (0..N)
+        .max_by(|&a, &b| covariance[a][a].total_cmp(&covariance[b][b]))
+        .unwrap();
+    let length = covariance[widest].iter().map(|v| v * v).sum::<f32>().sqrt();
+    let mut axis = covariance[widest].map(|v| v / length.max(f32::EPSILON));
+    for _ in 0..8 {
+        let mut next = [0.0f32; N];
+        for i in 0..N {
+            for j in 0..N {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < f32::EPSILON {
+            break;
+        }
+        axis = next.map(|v| v / length);
+    }
+
+    let (mut min_t, mut max_t) = (0.0f32, 0.0f32);
+    for pixel in pixels {
+        let t: f32 = (0..N).map(|c| (pixel[c] - mean[c]) * axis[c]).sum();
+        min_t = min_t.min(t);
+        max_t = max_t.max(t);
+    }
+    (
+        std::array::from_fn(|c| mean[c] + axis[c] * min_t),
+        std::array::from_fn(|c| mean[c] + axis[c] * max_t),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bcn_decode::{decode_bc7_block, decompress};
+    use image::{Rgba, Rgba32FImage};
+
+    fn bits(block: [u8; 16], offset: u32, count: u32) -> u32 {
+        ((u128::from_le_bytes(block) >> offset) & ((1 << count) - 1)) as u32
+    }
+
+    fn gradient() -> [[u8; 4]; 16] {
+        std::array::from_fn(|i| {
+            let i = i as u8;
+            [i * 16, 255 - i * 16, 128, 255 - i * 8]
+        })
+    }
+
+    fn max_error(a: &[[u8; 4]; 16], b: &[[u8; 4]; 16]) -> i32 {
+        a.iter()
+            .zip(b)
+            .flat_map(|(a, b)| (0..4).map(|c| (a[c] as i32 - b[c] as i32).abs()))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn bc7_mode6_layout() {
+        let block = Bc7Mode6 {
+            endpoints: [[1, 2, 3, 4], [5, 6, 7, 8]],
+            p_bits: [1, 0],
+            indices: std::array::from_fn(|i| i as u8 / 2),
+            error: 0,
+        }
+        .pack();
+        assert_eq!(bits(block, 0, 7), 0b1000000);
+        // Channels are stored R0 R1 G0 G1 B0 B1 A0 A1, 7 bits each
+        let channels: Vec<u32> = (0..8).map(|i| bits(block, 7 + 7 * i, 7)).collect();
+        assert_eq!(channels, [1, 5, 2, 6, 3, 7, 4, 8]);
+        assert_eq!(bits(block, 63, 1), 1);
+        assert_eq!(bits(block, 64, 1), 0);
+        // The anchor index only has 3 bits
+        assert_eq!(bits(block, 65, 3), 0);
+        assert_eq!(bits(block, 68, 4), 0);
+        assert_eq!(bits(block, 72, 4), 1);
+        assert_eq!(bits(block, 124, 4), 7);
+    }
+
+    #[test]
+    fn bc7_mode6_swaps_endpoints_for_anchor() {
+        let mode = Bc7Mode6 {
+            endpoints: [[10, 20, 30, 127], [100, 90, 80, 60]],
+            p_bits: [0, 1],
+            indices: std::array::from_fn(|i| 15 - i as u8),
+            error: 0,
+        };
+        let (a, b) = (mode.expanded(0), mode.expanded(1));
+        let expected: [[u8; 4]; 16] = std::array::from_fn(|i| {
+            let weight = BC7_WEIGHTS_4[mode.indices[i] as usize];
+            [0, 1, 2, 3].map(|c| bc7_interpolate(a[c], b[c], weight))
+        });
+        let block = mode.pack();
+        assert_eq!(bits(block, 65, 3), 0);
+        assert_eq!(decode_bc7_block(&block), expected);
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        let texels = gradient();
+        let decoded = decode_bc7_block(&encode_bc7_block(&texels));
+        assert!(max_error(&texels, &decoded) <= 8);
+
+        let flat = [[12, 200, 99, 255]; 16];
+        assert!(max_error(&flat, &decode_bc7_block(&encode_bc7_block(&flat))) <= 1);
+    }
+
+    /// A diagonal gradient between two colors with a little noise, like most texture blocks.
+    /// 8x6 so the last row of blocks is padded.
+    fn test_image() -> RgbaImage {
+        RgbaImage::from_fn(8, 6, |x, y| {
+            let t = (x + y) as i32 * 12;
+            let noise = ((x * 7 + y * 13) % 5) as i32 - 2;
+            let channel =
+                |base: i32, slope: i32| (base + t * slope / 4 + noise).clamp(0, 255) as u8;
+            Rgba([
+                channel(40, 4),
+                channel(200, -2),
+                channel(90, 1),
+                channel(255, -3),
+            ])
+        })
+    }
+
+    /// Largest difference of a channel after compressing and decompressing `image`.
+    fn round_trip_error(image: &RgbaImage, format: BcnFormat, channels: &[usize]) -> i32 {
+        let (width, height) = image.dimensions();
+        let data = compress_bcn(&DynamicImage::ImageRgba8(image.clone()), format);
+        assert_eq!(
+            data.len(),
+            width.div_ceil(4) as usize * height.div_ceil(4) as usize * format.block_bytes()
+        );
+        let decoded = decompress(&data, width, height, format).unwrap().to_rgba8();
+        image
+            .pixels()
+            .zip(decoded.pixels())
+            .flat_map(|(a, b)| channels.iter().map(|&c| (a[c] as i32 - b[c] as i32).abs()))
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn ldr_round_trips() {
+        let image = test_image();
+        let opaque = RgbaImage::from_fn(8, 6, |x, y| {
+            let [r, g, b, _] = image.get_pixel(x, y).0;
+            Rgba([r, g, b, 255])
+        });
+        // BC1 has 5 and 6 bit endpoints, the alpha of BC2 has 4 bits
+        assert!(round_trip_error(&opaque, BcnFormat::Bc1, &[0, 1, 2, 3]) <= 12);
+        assert!(round_trip_error(&image, BcnFormat::Bc2, &[0, 1, 2]) <= 12);
+        assert!(round_trip_error(&image, BcnFormat::Bc2, &[3]) <= 8);
+        assert!(round_trip_error(&image, BcnFormat::Bc3, &[0, 1, 2]) <= 12);
+        assert!(round_trip_error(&image, BcnFormat::Bc3, &[3]) <= 6);
+        assert!(round_trip_error(&image, BcnFormat::Bc7, &[0, 1, 2, 3]) <= 6);
+        for signed in [false, true] {
+            assert!(round_trip_error(&image, BcnFormat::Bc4 { signed }, &[0]) <= 6);
+            assert!(round_trip_error(&image, BcnFormat::Bc5 { signed }, &[0, 1]) <= 6);
+        }
+    }
+
+    #[test]
+    fn flat_blocks_are_exact() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 200, 100, 255]));
+        // The p-bit is shared by the channels, so odd and even values can't all be exact
+        assert!(round_trip_error(&image, BcnFormat::Bc7, &[0, 1, 2, 3]) <= 1);
+        assert_eq!(
+            round_trip_error(&image, BcnFormat::Bc4 { signed: false }, &[0]),
+            0
+        );
+        assert_eq!(
+            round_trip_error(&image, BcnFormat::Bc5 { signed: true }, &[0, 1]),
+            0
+        );
+    }
+
+    #[test]
+    fn bc1_punchthrough() {
+        let image = RgbaImage::from_fn(4, 4, |x, _| {
+            let alpha = if x == 0 { 0 } else { 255 };
+            Rgba([200, 100, 50, alpha])
+        });
+        let data = compress_bcn(&DynamicImage::ImageRgba8(image.clone()), BcnFormat::Bc1);
+        let decoded = decompress(&data, 4, 4, BcnFormat::Bc1).unwrap().to_rgba8();
+        for (x, y, pixel) in decoded.enumerate_pixels() {
+            if x == 0 {
+                assert_eq!(pixel.0, [0, 0, 0, 0]);
+            } else {
+                let expected = image.get_pixel(x, y).0;
+                assert_eq!(pixel[3], 255);
+                assert!((0..3).all(|c| (pixel[c] as i32 - expected[c] as i32).abs() <= 4));
+            }
+        }
+    }
+
+    /// Largest error relative to the value, ignoring values closer to 0 than `floor`.
+    fn bc6h_round_trip_error(image: &Rgba32FImage, signed: bool, floor: f32) -> f32 {
+        let format = BcnFormat::Bc6h { signed };
+        let (width, height) = image.dimensions();
+        let data = compress_bcn(&DynamicImage::ImageRgba32F(image.clone()), format);
+        let decoded = decompress(&data, width, height, format)
+            .unwrap()
+            .to_rgba32f();
+        image
+            .pixels()
+            .zip(decoded.pixels())
+            .flat_map(|(a, b)| (0..3).map(move |c| (a[c] - b[c]).abs() / a[c].abs().max(floor)))
+            .fold(0.0, f32::max)
+    }
+
+    #[test]
+    fn bc6h_round_trips() {
+        // A sky like gradient from dim to bright
+        let image = Rgba32FImage::from_fn(8, 8, |x, y| {
+            let t = (x + y) as f32 / 14.0;
+            Rgba([0.5 + 7.5 * t, 0.6 + 6.0 * t, 1.0 + 4.0 * t, 1.0])
+        });
+        // The indices interpolate the bits of the half floats, the blocks span about 2 stops
+        // so the 16 steps between the endpoints are up to 19% apart
+        assert!(bc6h_round_trip_error(&image, false, 0.5) < 0.1);
+        assert!(bc6h_round_trip_error(&image, true, 0.5) < 0.1);
+
+        let negative = Rgba32FImage::from_fn(8, 8, |x, y| {
+            let Rgba([r, g, b, a]) = *image.get_pixel(x, y);
+            Rgba([-r, -g, -b, a])
+        });
+        assert!(bc6h_round_trip_error(&negative, true, 0.5) < 0.1);
+
+        // A block going through 0 interpolates between the largest magnitudes, only the
+        // signs are kept. Negative values clamp to 0 when unsigned.
+        let signed = Rgba32FImage::from_fn(4, 4, |x, _| {
+            let v = x as f32 - 1.5;
+            Rgba([v, -v, 2.0 * v, 1.0])
+        });
+        let decode = |signed_format| {
+            let format = BcnFormat::Bc6h {
+                signed: signed_format,
+            };
+            let data = compress_bcn(&DynamicImage::ImageRgba32F(signed.clone()), format);
+            decompress(&data, 4, 4, format).unwrap().to_rgba32f()
+        };
+        let decoded = decode(true);
+        for (a, b) in signed.pixels().zip(decoded.pixels()) {
+            assert!(
+                (0..3).all(|c| a[c].signum() == b[c].signum()),
+                "{a:?} {b:?}"
+            );
+        }
+        let decoded = decode(false);
+        assert!(decoded.pixels().all(|p| p[0] >= 0.0 && p[1] >= 0.0));
+        assert_eq!(decoded.get_pixel(0, 0)[0], 0.0);
+    }
+}
@@ -0,0 +1,1055 @@
+//! CPU block decompression for every BCn format, so the mipmap generator can work on textures
+//! that were shipped compressed without a mip chain.
+
+use half::f16;
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+
+use crate::bcn::{bc1_palette, bc4_palette, BcnFormat, BC7_WEIGHTS_4};
+
+/// Decompresses the first mip level of a texture. Everything but BC6H decodes to RGBA8,
+/// BC4 and BC5 leave the channels they don't have at 0 like the GPU does.
+pub fn decompress(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    format: BcnFormat,
+) -> anyhow::Result<DynamicImage> {
+    let blocks_x = width.div_ceil(4);
+    let blocks_y = height.div_ceil(4);
+    let block_bytes = format.block_bytes();
+    let expected = (blocks_x * blocks_y) as usize * block_bytes;
+    if data.len() < expected {
+        return Err(anyhow::anyhow!(
+            "{format:?} image of {width}x{height} needs {expected} bytes, got {}",
+            data.len()
+        ));
+    }
+    let blocks = data[..expected].chunks_exact(block_bytes);
+    let block_origins = (0..blocks_y).flat_map(|by| (0..blocks_x).map(move |bx| (bx * 4, by * 4)));
+
+    if let BcnFormat::Bc6h { signed } = format {
+        let mut image = Rgba32FImage::new(width, height);
+        for (block, (x0, y0)) in blocks.zip(block_origins) {
+            let texels = decode_bc6h_block(block.try_into().unwrap(), signed);
+            for (i, texel) in texels.iter().enumerate() {
+                let (x, y) = (x0 + i as u32 % 4, y0 + i as u32 / 4);
+                if x < width && y < height {
+                    image.put_pixel(x, y, image::Rgba([texel[0], texel[1], texel[2], 1.0]));
+                }
+            }
+        }
+        return Ok(DynamicImage::ImageRgba32F(image));
+    }
+
+    let mut image = RgbaImage::new(width, height);
+    for (block, (x0, y0)) in blocks.zip(block_origins) {
+        let texels = match format {
+            BcnFormat::Bc1 => decode_bc1_block(block.try_into().unwrap(), true),
+            BcnFormat::Bc2 => decode_bc2_block(block.try_into().unwrap()),
+            BcnFormat::Bc3 => decode_bc3_block(block.try_into().unwrap()),
+            BcnFormat::Bc4 { signed } => {
+                decode_bc4_block(block.try_into().unwrap(), signed).map(|r| [r, 0, 0, 255])
+            }
+            BcnFormat::Bc5 { signed } => {
+                let red = decode_bc4_block(block[..8].try_into().unwrap(), signed);
+                let green = decode_bc4_block(block[8..].try_into().unwrap(), signed);
+                std::array::from_fn(|i| [red[i], green[i], 0, 255])
+            }
+            BcnFormat::Bc7 => decode_bc7_block(block.try_into().unwrap()),
+            BcnFormat::Bc6h { .. } => unreachable!(),
+        };
+        for (i, texel) in texels.iter().enumerate() {
+            let (x, y) = (x0 + i as u32 % 4, y0 + i as u32 / 4);
+            if x < width && y < height {
+                image.put_pixel(x, y, image::Rgba(*texel));
+            }
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(image))
+}
+
+pub fn decode_bc1_block(block: &[u8; 8], punchthrough: bool) -> [[u8; 4]; 16] {
+    let c0 = u16::from_le_bytes([block[0], block[1]]);
+    let c1 = u16::from_le_bytes([block[2], block[3]]);
+    let palette = bc1_palette(c0, c1, punchthrough);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    std::array::from_fn(|i| palette[(indices >> (2 * i) & 0b11) as usize])
+}
+
+pub fn decode_bc2_block(block: &[u8; 16]) -> [[u8; 4]; 16] {
+    let alpha = u64::from_le_bytes(block[..8].try_into().unwrap());
+    let mut texels = decode_bc1_block(block[8..].try_into().unwrap(), false);
+    for (i, texel) in texels.iter_mut().enumerate() {
+        texel[3] = (alpha >> (4 * i) & 0xf) as u8 * 17;
+    }
+    texels
+}
+
+pub fn decode_bc3_block(block: &[u8; 16]) -> [[u8; 4]; 16] {
+    let alpha = decode_bc4_block(block[..8].try_into().unwrap(), false);
+    let mut texels = decode_bc1_block(block[8..].try_into().unwrap(), false);
+    for (texel, alpha) in texels.iter_mut().zip(alpha) {
+        texel[3] = alpha;
+    }
+    texels
+}
+
+/// Signed blocks are offset by 128 so they fit in a `u8`, -1.0 becomes 1 and 1.0 becomes 255.
+pub fn decode_bc4_block(block: &[u8; 8], signed: bool) -> [u8; 16] {
+    let palette = if signed {
+        let (r0, r1) = (block[0] as i8 as i32, block[1] as i8 as i32);
+        let (r0, r1) = (r0.max(-127), r1.max(-127));
+        let palette: [i32; 8] = if r0 > r1 {
+            [
+                r0,
+                r1,
+                (6 * r0 + r1) / 7,
+                (5 * r0 + 2 * r1) / 7,
+                (4 * r0 + 3 * r1) / 7,
+                (3 * r0 + 4 * r1) / 7,
+                (2 * r0 + 5 * r1) / 7,
+                (r0 + 6 * r1) / 7,
+            ]
+        } else {
+            [
+                r0,
+                r1,
+                (4 * r0 + r1) / 5,
+                (3 * r0 + 2 * r1) / 5,
+                (2 * r0 + 3 * r1) / 5,
+                (r0 + 4 * r1) / 5,
+                -127,
+                127,
+            ]
+        };
+        palette.map(|value| (value + 128) as u8)
+    } else {
+        bc4_palette(block[0], block[1])
+    };
+    let mut indices = [0u8; 8];
+    indices[..6].copy_from_slice(&block[2..]);
+    let indices = u64::from_le_bytes(indices);
+    std::array::from_fn(|i| palette[(indices >> (3 * i) & 0b111) as usize])
+}
+
+/// Reads a block from its least significant bit up.
+struct BitReader(u128);
+
+impl BitReader {
+    fn new(block: &[u8; 16]) -> Self {
+        Self(u128::from_le_bytes(*block))
+    }
+
+    fn read(&mut self, bits: u32) -> u32 {
+        let value = (self.0 & ((1u128 << bits) - 1)) as u32;
+        self.0 >>= bits;
+        value
+    }
+}
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+
+fn weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &BC7_WEIGHTS_2,
+        3 => &BC7_WEIGHTS_3,
+        _ => &BC7_WEIGHTS_4,
+    }
+}
+
+/// Subset of every texel for the 2 subset partitions, one bit per texel.
+pub const PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80, 0xc800, 0xffec, 0xfe80, 0xe800,
+    0xffe8, 0xff00, 0xfff0, 0xf000, 0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c, 0xaaaa, 0xf0f0, 0x5a5a, 0x33cc,
+    0x3c3c, 0x55aa, 0x9696, 0xa55a, 0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c, 0x9336, 0x9cc6, 0x817e, 0xe718,
+    0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+/// Subset of every texel for the 3 subset partitions, two bits per texel.
+const PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// Texel whose index has an implicit 0 high bit, for the second subset of 2 subset partitions.
+pub const ANCHORS_2: [usize; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Same for the second and third subsets of 3 subset partitions.
+const ANCHORS_3: [[usize; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+/// Subset of texel `i` and whether its index is stored with one bit less.
+fn subset(subsets: u32, partition: usize, i: usize) -> (usize, bool) {
+    match subsets {
+        1 => (0, i == 0),
+        2 => {
+            let subset = (PARTITIONS_2[partition] >> i & 1) as usize;
+            (subset, i == 0 || i == ANCHORS_2[partition])
+        }
+        _ => {
+            let subset = (PARTITIONS_3[partition] >> (2 * i) & 0b11) as usize;
+            let anchor = i == 0 || i == ANCHORS_3[0][partition] || i == ANCHORS_3[1][partition];
+            (subset, anchor)
+        }
+    }
+}
+
+struct Bc7Mode {
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    /// One p-bit per endpoint.
+    endpoint_p_bits: bool,
+    /// One p-bit per subset, shared by both of its endpoints.
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+#[allow(clippy::too_many_arguments)]
+const fn bc7_mode(
+    subsets: u32,
+    partition_bits: u32,
+    rotation_bits: u32,
+    index_selection_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_p_bits: bool,
+    shared_p_bits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        index_selection_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_p_bits,
+        shared_p_bits,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, 0, 0, 4, 0, true, false, 3, 0),
+    bc7_mode(2, 6, 0, 0, 6, 0, false, true, 3, 0),
+    bc7_mode(3, 6, 0, 0, 5, 0, false, false, 2, 0),
+    bc7_mode(2, 6, 0, 0, 7, 0, true, false, 2, 0),
+    bc7_mode(1, 0, 2, 1, 5, 6, false, false, 2, 3),
+    bc7_mode(1, 0, 2, 0, 7, 8, false, false, 2, 2),
+    bc7_mode(1, 0, 0, 0, 7, 7, true, false, 4, 0),
+    bc7_mode(2, 6, 0, 0, 5, 5, true, false, 2, 0),
+];
+
+/// Replicates the high bits of a `bits` wide value into the low bits of a byte.
+fn expand_bits(value: u32, bits: u32) -> u8 {
+    let value = value << (8 - bits);
+    (value | value >> bits) as u8
+}
+
+fn interpolate(e0: u32, e1: u32, weight: u32) -> u32 {
+    ((64 - weight) * e0 + weight * e1 + 32) >> 6
+}
+
+pub fn decode_bc7_block(block: &[u8; 16]) -> [[u8; 4]; 16] {
+    if block[0] == 0 {
+        // Reserved mode, decodes to transparent black
+        return [[0; 4]; 16];
+    }
+    let mode_index = block[0].trailing_zeros();
+    let mode = &BC7_MODES[mode_index as usize];
+    let mut bits = BitReader::new(block);
+    bits.read(mode_index + 1);
+
+    let partition = bits.read(mode.partition_bits) as usize;
+    let rotation = bits.read(mode.rotation_bits);
+    let index_selection = bits.read(mode.index_selection_bits);
+
+    let endpoint_count = 2 * mode.subsets as usize;
+    let mut endpoints = [[0u32; 4]; 6];
+    for c in 0..3 {
+        for endpoint in &mut endpoints[..endpoint_count] {
+            endpoint[c] = bits.read(mode.color_bits);
+        }
+    }
+    for endpoint in &mut endpoints[..endpoint_count] {
+        endpoint[3] = bits.read(mode.alpha_bits);
+    }
+
+    let (mut color_bits, mut alpha_bits) = (mode.color_bits, mode.alpha_bits);
+    if mode.endpoint_p_bits || mode.shared_p_bits {
+        let mut p_bits = [0u32; 6];
+        if mode.endpoint_p_bits {
+            for p in &mut p_bits[..endpoint_count] {
+                *p = bits.read(1);
+            }
+        } else {
+            for s in 0..mode.subsets as usize {
+                let p = bits.read(1);
+                p_bits[2 * s] = p;
+                p_bits[2 * s + 1] = p;
+            }
+        }
+        for (endpoint, p) in endpoints.iter_mut().zip(p_bits) {
+            for (c, value) in endpoint.iter_mut().enumerate() {
+                if c < 3 || mode.alpha_bits > 0 {
+                    *value = *value << 1 | p;
+                }
+            }
+        }
+        color_bits += 1;
+        if alpha_bits > 0 {
+            alpha_bits += 1;
+        }
+    }
+    let endpoints = endpoints.map(|endpoint| {
+        [
+            expand_bits(endpoint[0], color_bits) as u32,
+            expand_bits(endpoint[1], color_bits) as u32,
+            expand_bits(endpoint[2], color_bits) as u32,
+            if alpha_bits > 0 {
+                expand_bits(endpoint[3], alpha_bits) as u32
+            } else {
+                255
+            },
+        ]
+    });
+
+    let mut indices = [0u32; 16];
+    let mut subsets = [0usize; 16];
+    for (i, index) in indices.iter_mut().enumerate() {
+        let (subset, anchor) = subset(mode.subsets, partition, i);
+        subsets[i] = subset;
+        *index = bits.read(mode.index_bits - anchor as u32);
+    }
+    let mut secondary_indices = [0u32; 16];
+    if mode.secondary_index_bits > 0 {
+        for (i, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.read(mode.secondary_index_bits - (i == 0) as u32);
+        }
+    }
+
+    std::array::from_fn(|i| {
+        let (e0, e1) = (endpoints[2 * subsets[i]], endpoints[2 * subsets[i] + 1]);
+        let (color_weight, alpha_weight) = if mode.secondary_index_bits == 0 {
+            let weight = weights(mode.index_bits)[indices[i] as usize];
+            (weight, weight)
+        } else {
+            let primary = weights(mode.index_bits)[indices[i] as usize];
+            let secondary = weights(mode.secondary_index_bits)[secondary_indices[i] as usize];
+            if index_selection == 0 {
+                (primary, secondary)
+            } else {
+                (secondary, primary)
+            }
+        };
+        let mut texel = [0, 1, 2, 3].map(|c| {
+            let weight = if c < 3 { color_weight } else { alpha_weight };
+            interpolate(e0[c], e1[c], weight) as u8
+        });
+        if rotation > 0 {
+            texel.swap(3, rotation as usize - 1);
+        }
+        texel
+    })
+}
+
+// Endpoint fields of the BC6H layouts, endpoint * 3 + channel. W and X are the endpoints of the
+// first region, Y and Z the ones of the second.
+const RW: u8 = 0;
+const GW: u8 = 1;
+const BW: u8 = 2;
+const RX: u8 = 3;
+const GX: u8 = 4;
+const BX: u8 = 5;
+const RY: u8 = 6;
+const GY: u8 = 7;
+const BY: u8 = 8;
+const RZ: u8 = 9;
+const GZ: u8 = 10;
+const BZ: u8 = 11;
+
+struct Bc6hMode {
+    transformed: bool,
+    regions: usize,
+    endpoint_bits: u32,
+    delta_bits: [u32; 3],
+    /// `(field, first bit, last bit)` in the order they are stored, the bits go down when the
+    /// first one is higher.
+    layout: &'static [(u8, u8, u8)],
+}
+
+/// Modes by the value of their mode bits, 2 bits for the first two modes and 5 for the others.
+#[rustfmt::skip]
+fn bc6h_mode(mode_bits: u32) -> Option<Bc6hMode> {
+    let mode = |transformed, regions, endpoint_bits, delta_bits, layout| Bc6hMode {
+        transformed,
+        regions,
+        endpoint_bits,
+        delta_bits,
+        layout,
+    };
+    Some(match mode_bits {
+        0 => mode(true, 2, 10, [5, 5, 5], &[
+            (GY, 4, 4), (BY, 4, 4), (BZ, 4, 4), (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 4),
+            (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BZ, 1, 1),
+            (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        ]),
+        1 => mode(true, 2, 7, [6, 6, 6], &[
+            (GY, 5, 5), (GZ, 4, 4), (GZ, 5, 5), (RW, 0, 6), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4),
+            (GW, 0, 6), (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 6), (BZ, 3, 3), (BZ, 5, 5),
+            (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3),
+            (RY, 0, 5), (RZ, 0, 5),
+        ]),
+        2 => mode(true, 2, 11, [5, 4, 4], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 4), (RW, 10, 10), (GY, 0, 3), (GX, 0, 3),
+            (GW, 10, 10), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 3), (BW, 10, 10), (BZ, 1, 1), (BY, 0, 3),
+            (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        ]),
+        6 => mode(true, 2, 11, [4, 5, 4], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 10, 10), (GZ, 4, 4), (GY, 0, 3),
+            (GX, 0, 4), (GW, 10, 10), (GZ, 0, 3), (BX, 0, 3), (BW, 10, 10), (BZ, 1, 1), (BY, 0, 3),
+            (RY, 0, 3), (BZ, 0, 0), (BZ, 2, 2), (RZ, 0, 3), (GY, 4, 4), (BZ, 3, 3),
+        ]),
+        10 => mode(true, 2, 11, [4, 4, 5], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 10, 10), (BY, 4, 4), (GY, 0, 3),
+            (GX, 0, 3), (GW, 10, 10), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BW, 10, 10), (BY, 0, 3),
+            (RY, 0, 3), (BZ, 1, 1), (BZ, 2, 2), (RZ, 0, 3), (BZ, 4, 4), (BZ, 3, 3),
+        ]),
+        14 => mode(true, 2, 9, [5, 5, 5], &[
+            (RW, 0, 8), (BY, 4, 4), (GW, 0, 8), (GY, 4, 4), (BW, 0, 8), (BZ, 4, 4), (RX, 0, 4),
+            (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3), (BX, 0, 4), (BZ, 1, 1),
+            (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        ]),
+        18 => mode(true, 2, 8, [6, 5, 5], &[
+            (RW, 0, 7), (GZ, 4, 4), (BY, 4, 4), (GW, 0, 7), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 7),
+            (BZ, 3, 3), (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0), (GZ, 0, 3),
+            (BX, 0, 4), (BZ, 1, 1), (BY, 0, 3), (RY, 0, 5), (RZ, 0, 5),
+        ]),
+        22 => mode(true, 2, 8, [5, 6, 5], &[
+            (RW, 0, 7), (BZ, 0, 0), (BY, 4, 4), (GW, 0, 7), (GY, 5, 5), (GY, 4, 4), (BW, 0, 7),
+            (GZ, 5, 5), (BZ, 4, 4), (RX, 0, 4), (GZ, 4, 4), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3),
+            (BX, 0, 4), (BZ, 1, 1), (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        ]),
+        26 => mode(true, 2, 8, [5, 5, 6], &[
+            (RW, 0, 7), (BZ, 1, 1), (BY, 4, 4), (GW, 0, 7), (BY, 5, 5), (GY, 4, 4), (BW, 0, 7),
+            (BZ, 5, 5), (BZ, 4, 4), (RX, 0, 4), (GZ, 4, 4), (GY, 0, 3), (GX, 0, 4), (BZ, 0, 0),
+            (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3), (RY, 0, 4), (BZ, 2, 2), (RZ, 0, 4), (BZ, 3, 3),
+        ]),
+        30 => mode(false, 2, 6, [6, 6, 6], &[
+            (RW, 0, 5), (GZ, 4, 4), (BZ, 0, 0), (BZ, 1, 1), (BY, 4, 4), (GW, 0, 5), (GY, 5, 5),
+            (BY, 5, 5), (BZ, 2, 2), (GY, 4, 4), (BW, 0, 5), (GZ, 5, 5), (BZ, 3, 3), (BZ, 5, 5),
+            (BZ, 4, 4), (RX, 0, 5), (GY, 0, 3), (GX, 0, 5), (GZ, 0, 3), (BX, 0, 5), (BY, 0, 3),
+            (RY, 0, 5), (RZ, 0, 5),
+        ]),
+        3 => mode(false, 1, 10, [10, 10, 10], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 9), (GX, 0, 9), (BX, 0, 9),
+        ]),
+        7 => mode(true, 1, 11, [9, 9, 9], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 8), (RW, 10, 10), (GX, 0, 8),
+            (GW, 10, 10), (BX, 0, 8), (BW, 10, 10),
+        ]),
+        11 => mode(true, 1, 12, [8, 8, 8], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 7), (RW, 11, 10), (GX, 0, 7),
+            (GW, 11, 10), (BX, 0, 7), (BW, 11, 10),
+        ]),
+        15 => mode(true, 1, 16, [4, 4, 4], &[
+            (RW, 0, 9), (GW, 0, 9), (BW, 0, 9), (RX, 0, 3), (RW, 15, 10), (GX, 0, 3),
+            (GW, 15, 10), (BX, 0, 3), (BW, 15, 10),
+        ]),
+        _ => return None,
+    })
+}
+
+fn sign_extend(value: i32, bits: u32) -> i32 {
+    let shift = 32 - bits;
+    value << shift >> shift
+}
+
+/// Scales a quantized endpoint to the 16 bit range the palette is interpolated in.
+pub fn bc6h_unquantize(value: i32, bits: u32, signed: bool) -> i32 {
+    if signed {
+        if bits >= 16 {
+            return value;
+        }
+        let magnitude = value.abs();
+        let unquantized = if magnitude == 0 {
+            0
+        } else if magnitude >= (1 << (bits - 1)) - 1 {
+            0x7fff
+        } else {
+            ((magnitude << 15) + 0x4000) >> (bits - 1)
+        };
+        unquantized * value.signum()
+    } else if bits >= 15 {
+        value
+    } else if value == 0 {
+        0
+    } else if value == (1 << bits) - 1 {
+        0xffff
+    } else {
+        ((value << 16) + 0x8000) >> bits
+    }
+}
+
+/// Turns an interpolated value into the bits of a half float.
+pub fn bc6h_finish_unquantize(value: i32, signed: bool) -> u16 {
+    if !signed {
+        ((value * 31) >> 6) as u16
+    } else if value < 0 {
+        0x8000 | ((-value * 31) >> 5) as u16
+    } else {
+        ((value * 31) >> 5) as u16
+    }
+}
+
+pub fn decode_bc6h_block(block: &[u8; 16], signed: bool) -> [[f32; 3]; 16] {
+    let mut bits = BitReader::new(block);
+    let mut mode_bits = bits.read(2);
+    if mode_bits > 1 {
+        mode_bits |= bits.read(3) << 2;
+    }
+    let Some(mode) = bc6h_mode(mode_bits) else {
+        // Reserved modes decode to black
+        return [[0.0; 3]; 16];
+    };
+
+    let mut fields = [0i32; 12];
+    for &(field, first, last) in mode.layout {
+        if first <= last {
+            for bit in first..=last {
+                fields[field as usize] |= (bits.read(1) as i32) << bit;
+            }
+        } else {
+            for bit in (last..=first).rev() {
+                fields[field as usize] |= (bits.read(1) as i32) << bit;
+            }
+        }
+    }
+    let partition = if mode.regions == 2 {
+        bits.read(5) as usize
+    } else {
+        0
+    };
+
+    let endpoint_mask = (1 << mode.endpoint_bits) - 1;
+    let endpoint_count = 2 * mode.regions;
+    let mut endpoints = [[0i32; 3]; 4];
+    for c in 0..3 {
+        let base = fields[c];
+        endpoints[0][c] = if signed {
+            sign_extend(base, mode.endpoint_bits)
+        } else {
+            base
+        };
+        for e in 1..endpoint_count {
+            let value = fields[e * 3 + c];
+            endpoints[e][c] = if mode.transformed {
+                let delta = sign_extend(value, mode.delta_bits[c]);
+                let value = (base + delta) & endpoint_mask;
+                if signed {
+                    sign_extend(value, mode.endpoint_bits)
+                } else {
+                    value
+                }
+            } else if signed {
+                sign_extend(value, mode.endpoint_bits)
+            } else {
+                value
+            };
+        }
+    }
+    let endpoints = endpoints.map(|e| e.map(|v| bc6h_unquantize(v, mode.endpoint_bits, signed)));
+
+    let index_bits = if mode.regions == 2 { 3 } else { 4 };
+    std::array::from_fn(|i| {
+        let (region, anchor) = subset(mode.regions as u32, partition, i);
+        let index = bits.read(index_bits - anchor as u32);
+        let weight = weights(index_bits)[index as usize] as i32;
+        let (e0, e1) = (endpoints[2 * region], endpoints[2 * region + 1]);
+        [0, 1, 2].map(|c| {
+            let value = ((64 - weight) * e0[c] + weight * e1[c] + 32) >> 6;
+            f16::from_bits(bc6h_finish_unquantize(value, signed)).to_f32()
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Packs `(value, bits)` fields from the least significant bit up, like blocks are stored.
+    fn pack(fields: &[(u32, u32)]) -> [u8; 16] {
+        let mut block = 0u128;
+        let mut offset = 0;
+        for &(value, bits) in fields {
+            assert!(value < 1 << bits, "{value} doesn't fit in {bits} bits");
+            block |= (value as u128) << offset;
+            offset += bits;
+        }
+        assert_eq!(offset, 128);
+        block.to_le_bytes()
+    }
+
+    /// Index fields, the anchor texels are stored with one bit less.
+    fn indices(values: [u32; 16], bits: u32, anchors: &[usize]) -> Vec<(u32, u32)> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (value, bits - anchors.contains(&i) as u32))
+            .collect()
+    }
+
+    fn half(bits: [u16; 3]) -> [f32; 3] {
+        bits.map(|bits| f16::from_bits(bits).to_f32())
+    }
+
+    #[test]
+    fn bc1_known_answer() {
+        // Red and blue, indices 0 1 2 3 on the first row
+        let block = [0x00, 0xf8, 0x1f, 0x00, 0b11100100, 0, 0, 0];
+        let texels = decode_bc1_block(&block, true);
+        assert_eq!(
+            texels[..4],
+            [
+                [255, 0, 0, 255],
+                [0, 0, 255, 255],
+                [170, 0, 85, 255],
+                [85, 0, 170, 255]
+            ]
+        );
+        assert_eq!(texels[4], [255, 0, 0, 255]);
+
+        // Endpoints in increasing order give 3 colors and transparent black
+        let block = [0x1f, 0x00, 0x00, 0xf8, 0b11100100, 0, 0, 0];
+        let texels = decode_bc1_block(&block, true);
+        assert_eq!(
+            texels[..4],
+            [
+                [0, 0, 255, 255],
+                [255, 0, 0, 255],
+                [127, 0, 127, 255],
+                [0, 0, 0, 0]
+            ]
+        );
+        // Unless the block is from BC2 or BC3
+        let texels = decode_bc1_block(&block, false);
+        assert_eq!(texels[2..4], [[85, 0, 170, 255], [170, 0, 85, 255]]);
+    }
+
+    #[test]
+    fn bc2_known_answer() {
+        let mut block = [0u8; 16];
+        // 4 bit alpha 0, 1, 8 and 15
+        block[0] = 0x10;
+        block[1] = 0xf8;
+        block[8..].copy_from_slice(&[0xff, 0xff, 0x00, 0x00, 0, 0, 0, 0]);
+        let alpha: Vec<u8> = decode_bc2_block(&block)[..4].iter().map(|t| t[3]).collect();
+        assert_eq!(alpha, [0, 17, 136, 255]);
+        assert_eq!(decode_bc2_block(&block)[0][..3], [255, 255, 255]);
+    }
+
+    #[test]
+    fn bc3_known_answer() {
+        let mut block = [0u8; 16];
+        // Alpha 200 to 100, indices 0 1 2 7
+        block[..8].copy_from_slice(&[200, 100, 0b10_001_000, 0b1110, 0, 0, 0, 0]);
+        block[8..].copy_from_slice(&[0x00, 0xf8, 0x00, 0xf8, 0, 0, 0, 0]);
+        let texels = decode_bc3_block(&block);
+        let alpha: Vec<u8> = texels[..4].iter().map(|t| t[3]).collect();
+        assert_eq!(alpha, [200, 100, 185, 114]);
+        assert_eq!(texels[0][..3], [255, 0, 0]);
+    }
+
+    #[test]
+    fn bc4_known_answer() {
+        // 8 values, index 2 is 6/7 of the way to the first endpoint
+        let block = [200, 100, 0b10_001_000, 0b1110, 0, 0, 0, 0];
+        assert_eq!(decode_bc4_block(&block, false)[..4], [200, 100, 185, 114]);
+        // 6 values plus 0 and 255
+        let block = [100, 200, 0b10_111_110, 0, 0, 0, 0, 0];
+        assert_eq!(decode_bc4_block(&block, false)[..3], [0, 255, 120]);
+    }
+
+    #[test]
+    fn bc4_signed_known_answer() {
+        // 127 and -127, offset by 128 when decoded
+        let block = [0x7f, 0x81, 0b10_001_000, 0b1110, 0, 0, 0, 0];
+        assert_eq!(decode_bc4_block(&block, true)[..4], [255, 1, 218, 38]);
+        // -128 decodes like -127
+        let block = [0x80, 0x7f, 0b00_001_000, 0, 0, 0, 0, 0];
+        assert_eq!(decode_bc4_block(&block, true)[..2], [1, 255]);
+        // 6 values plus -1.0 and 1.0
+        let block = [0x00, 0x40, 0b10_111_110, 0, 0, 0, 0, 0];
+        assert_eq!(decode_bc4_block(&block, true)[..3], [1, 255, 140]);
+    }
+
+    #[test]
+    fn bc5_decodes_two_channels() {
+        let mut data = [0u8; 16];
+        data[..8].copy_from_slice(&[0x7f, 0x81, 0, 0, 0, 0, 0, 0]);
+        data[8..].copy_from_slice(&[0x81, 0x7f, 0, 0, 0, 0, 0, 0]);
+        let image = decompress(&data, 4, 4, BcnFormat::Bc5 { signed: true })
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(image.get_pixel(0, 0).0, [255, 1, 0, 255]);
+    }
+
+    #[test]
+    fn bc7_mode0_known_answer() {
+        // Partition 0 has subsets 0 0 1 1 / 0 0 1 1 / 0 2 2 1 / 2 2 2 2, anchors 0, 3 and 15
+        let mut fields = vec![(1, 1), (0, 4)];
+        for channel in [
+            [0, 15, 15, 15, 0, 0],
+            [0, 15, 0, 0, 0, 0],
+            [0, 15, 0, 0, 15, 15],
+        ] {
+            fields.extend(channel.map(|value| (value, 4)));
+        }
+        fields.extend([0, 1, 1, 1, 0, 0].map(|p| (p, 1)));
+        let index_values = [0, 7, 0, 0, 3, 4, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0];
+        fields.extend(indices(index_values, 3, &[0, 3, 15]));
+
+        let gray = |v| [v, v, v, 255];
+        let (red, blue) = ([255, 8, 8, 255], [0, 0, 247, 255]);
+        #[rustfmt::skip]
+        let expected = [
+            gray(0), gray(255), red, red,
+            gray(108), gray(147), red, red,
+            gray(36), blue, blue, red,
+            blue, blue, blue, blue,
+        ];
+        assert_eq!(decode_bc7_block(&pack(&fields)), expected);
+    }
+
+    #[test]
+    fn bc7_mode1_known_answer() {
+        // Partition 13 is the top and bottom half, anchors 0 and 15
+        let mut fields = vec![(0b10, 2), (13, 6)];
+        for channel in [[63, 0, 32, 32], [0, 63, 16, 16], [0, 0, 8, 8]] {
+            fields.extend(channel.map(|value| (value, 6)));
+        }
+        // One p-bit per subset
+        fields.extend([(1, 1), (0, 1)]);
+        let index_values = [0, 7, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        fields.extend(indices(index_values, 3, &[0, 15]));
+
+        let decoded = decode_bc7_block(&pack(&fields));
+        assert_eq!(decoded[0], [255, 2, 2, 255]);
+        assert_eq!(decoded[1], [2, 255, 2, 255]);
+        assert_eq!(decoded[2], [148, 109, 2, 255]);
+        assert_eq!(decoded[3..8], [[255, 2, 2, 255]; 5]);
+        assert_eq!(decoded[8..], [[129, 64, 32, 255]; 8]);
+    }
+
+    #[test]
+    fn bc7_mode2_known_answer() {
+        let mut fields = vec![(0b100, 3), (0, 6)];
+        for channel in [
+            [31, 0, 16, 16, 1, 1],
+            [0, 0, 16, 16, 2, 2],
+            [0, 31, 16, 16, 3, 3],
+        ] {
+            fields.extend(channel.map(|value| (value, 5)));
+        }
+        let index_values = [0, 3, 0, 0, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        fields.extend(indices(index_values, 2, &[0, 3, 15]));
+
+        let (gray, dark) = ([132, 132, 132, 255], [8, 16, 24, 255]);
+        #[rustfmt::skip]
+        let expected = [
+            [255, 0, 0, 255], [0, 0, 255, 255], gray, gray,
+            [171, 0, 84, 255], [84, 0, 171, 255], gray, gray,
+            [255, 0, 0, 255], dark, dark, gray,
+            dark, dark, dark, dark,
+        ];
+        assert_eq!(decode_bc7_block(&pack(&fields)), expected);
+    }
+
+    #[test]
+    fn bc7_mode3_known_answer() {
+        let mut fields = vec![(0b1000, 4), (13, 6)];
+        for channel in [[127, 0, 64, 64], [127, 0, 32, 32], [127, 0, 0, 0]] {
+            fields.extend(channel.map(|value| (value, 7)));
+        }
+        // One p-bit per endpoint
+        fields.extend([1, 0, 0, 1].map(|p| (p, 1)));
+        let index_values = [0, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3, 0];
+        fields.extend(indices(index_values, 2, &[0, 15]));
+
+        let decoded = decode_bc7_block(&pack(&fields));
+        let gray = |v| [v, v, v, 255];
+        assert_eq!(decoded[..4], [gray(255), gray(0), gray(171), gray(84)]);
+        assert_eq!(decoded[4..8], [gray(255); 4]);
+        assert_eq!(decoded[8], [128, 64, 0, 255]);
+        assert_eq!(decoded[14], [129, 65, 1, 255]);
+        assert_eq!(decoded[15], [128, 64, 0, 255]);
+    }
+
+    /// Mode 4 with red to green, transparent to opaque.
+    fn bc7_mode4(rotation: u32, index_selection: u32) -> [u8; 16] {
+        let mut fields = vec![(0b10000, 5), (rotation, 2), (index_selection, 1)];
+        fields.extend([
+            (31, 5),
+            (0, 5),
+            (0, 5),
+            (31, 5),
+            (0, 5),
+            (0, 5),
+            (0, 6),
+            (63, 6),
+        ]);
+        fields.extend(indices(
+            [0, 3, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            2,
+            &[0],
+        ));
+        fields.extend(indices(
+            [0, 7, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            3,
+            &[0],
+        ));
+        pack(&fields)
+    }
+
+    #[test]
+    fn bc7_mode4_known_answer() {
+        let decoded = decode_bc7_block(&bc7_mode4(0, 0));
+        assert_eq!(decoded[0], [255, 0, 0, 0]);
+        assert_eq!(decoded[1], [0, 255, 0, 255]);
+        // The 2 bit index picks the color and the 3 bit one the alpha
+        assert_eq!(decoded[2], [171, 84, 0, 147]);
+        assert_eq!(decoded[3], [255, 0, 0, 0]);
+
+        // Now the 3 bit index picks the color, and red is swapped with alpha
+        let decoded = decode_bc7_block(&bc7_mode4(1, 1));
+        assert_eq!(decoded[0], [0, 0, 0, 255]);
+        assert_eq!(decoded[1], [255, 255, 0, 0]);
+        assert_eq!(decoded[2], [84, 147, 0, 108]);
+    }
+
+    #[test]
+    fn bc7_mode5_known_answer() {
+        let block = |rotation| {
+            let mut fields = vec![(0b100000, 6), (rotation, 2)];
+            fields.extend([(127, 7), (0, 7), (0, 7), (127, 7), (64, 7), (64, 7)]);
+            fields.extend([(255, 8), (0, 8)]);
+            fields.extend(indices(
+                [0, 3, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                2,
+                &[0],
+            ));
+            fields.extend(indices(
+                [0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+                2,
+                &[0],
+            ));
+            pack(&fields)
+        };
+        let decoded = decode_bc7_block(&block(0));
+        assert_eq!(decoded[0], [255, 0, 129, 255]);
+        assert_eq!(decoded[1], [0, 255, 129, 255]);
+        assert_eq!(decoded[2], [171, 84, 129, 84]);
+        assert_eq!(decoded[15], [255, 0, 129, 255]);
+
+        // Green swapped with alpha
+        let decoded = decode_bc7_block(&block(2));
+        assert_eq!(decoded[0], [255, 255, 129, 0]);
+        assert_eq!(decoded[2], [171, 84, 129, 84]);
+    }
+
+    #[test]
+    fn bc7_mode6_known_answer() {
+        let mut fields = vec![(0b1000000, 7)];
+        fields.extend([
+            (127, 7),
+            (0, 7),
+            (0, 7),
+            (0, 7),
+            (0, 7),
+            (127, 7),
+            (127, 7),
+            (0, 7),
+        ]);
+        fields.extend([(1, 1), (0, 1)]);
+        fields.extend(indices(
+            [0, 15, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            4,
+            &[0],
+        ));
+
+        let decoded = decode_bc7_block(&pack(&fields));
+        assert_eq!(decoded[0], [255, 1, 1, 255]);
+        assert_eq!(decoded[1], [0, 0, 254, 0]);
+        assert_eq!(decoded[2], [120, 0, 135, 120]);
+        assert_eq!(decoded[3..], [[255, 1, 1, 255]; 13]);
+    }
+
+    #[test]
+    fn bc7_mode7_known_answer() {
+        let mut fields = vec![(0b10000000, 8), (13, 6)];
+        for channel in [[31, 0, 16, 16], [31, 0, 8, 8], [31, 0, 4, 4], [31, 0, 0, 0]] {
+            fields.extend(channel.map(|value| (value, 5)));
+        }
+        fields.extend([1, 0, 0, 0].map(|p| (p, 1)));
+        let index_values = [0, 3, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        fields.extend(indices(index_values, 2, &[0, 15]));
+
+        let decoded = decode_bc7_block(&pack(&fields));
+        assert_eq!(decoded[..4], [[255; 4], [0; 4], [171; 4], [84; 4]]);
+        assert_eq!(decoded[4..8], [[255; 4]; 4]);
+        assert_eq!(decoded[8..], [[130, 65, 32, 0]; 8]);
+    }
+
+    #[test]
+    fn bc7_reserved_mode() {
+        assert_eq!(decode_bc7_block(&[0; 16]), [[0; 4]; 16]);
+    }
+
+    #[test]
+    fn bc6h_mode11_known_answer() {
+        // 0b00011 is a single region with two 10 bit endpoints
+        let mut fields = vec![(0b00011, 5)];
+        fields.extend([495, 0, 1023, 0, 495, 0].map(|value| (value, 10)));
+        fields.extend(indices(
+            [0, 15, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            4,
+            &[0],
+        ));
+
+        let decoded = decode_bc6h_block(&pack(&fields), false);
+        assert_eq!(decoded[0], half([0x3c00, 0x0000, 0x7bff]));
+        assert_eq!(decoded[0], [1.0, 0.0, 65504.0]);
+        assert_eq!(decoded[1], [0.0, 1.0, 0.0]);
+        assert_eq!(decoded[2], half([0x1c20, 0x1fe0, 0x3a20]));
+    }
+
+    #[test]
+    fn bc6h_mode11_signed_known_answer() {
+        // -247 as 10 bits, 511 is the largest value
+        let mut fields = vec![(0b00011, 5)];
+        fields.extend([247, 1024 - 247, 0, 0, 0, 511].map(|value| (value, 10)));
+        fields.extend(indices(
+            [0, 15, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            4,
+            &[0],
+        ));
+
+        let decoded = decode_bc6h_block(&pack(&fields), true);
+        assert_eq!(decoded[0], half([0x3bf1, 0xbbf1, 0x0000]));
+        assert_eq!(decoded[1], half([0x0000, 0x0000, 0x7bff]));
+        assert_eq!(decoded[2], half([0x1c18, 0x9c18, 0x41df]));
+    }
+
+    #[test]
+    fn bc6h_mode12_known_answer() {
+        // 0b00111 has an 11 bit endpoint and a 9 bit delta, the top endpoint bits come later
+        let fields = [
+            (0b00111, 5),
+            (0, 10),
+            (100, 10),
+            (1023, 10),
+            (512 - 24, 9),
+            (1, 1),
+            (200, 9),
+            (0, 1),
+            (0, 9),
+            (1, 1),
+        ];
+        let mut fields = fields.to_vec();
+        fields.extend(indices(
+            [0, 15, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            4,
+            &[0],
+        ));
+
+        let decoded = decode_bc6h_block(&pack(&fields), false);
+        // (1024, 100, 2047) and (1000, 300, 2047)
+        assert_eq!(decoded[0], half([0x3e07, 0x0615, 0x7bff]));
+        assert_eq!(decoded[1], half([0x3c93, 0x1231, 0x7bff]));
+        assert_eq!(decoded[2], decoded[0]);
+    }
+
+    #[test]
+    fn bc6h_mode1_known_answer() {
+        // Two regions with a 10 bit endpoint and 5 bit deltas, fields in the order of the spec.
+        // Deltas are (3, -2, 0) for X, (-10, 15, 1) for Y and (5, 5, -16) for Z.
+        let (gy, by, bz) = (15u32, 1u32, 16u32);
+        let fields = [
+            (0b00, 2),
+            (gy >> 4 & 1, 1),
+            (by >> 4 & 1, 1),
+            (bz >> 4 & 1, 1),
+            (400, 10),
+            (500, 10),
+            (600, 10),
+            (3, 5),
+            // gz[4], 5 fits in 4 bits
+            (0, 1),
+            (gy & 0xf, 4),
+            (30, 5),
+            (bz & 1, 1),
+            (5, 4),
+            (0, 5),
+            (bz >> 1 & 1, 1),
+            (by & 0xf, 4),
+            (22, 5),
+            (bz >> 2 & 1, 1),
+            (5, 5),
+            (bz >> 3 & 1, 1),
+            // Partition 13 is the top and bottom half
+            (13, 5),
+        ];
+        let mut fields = fields.to_vec();
+        let index_values = [0, 7, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 7, 0];
+        fields.extend(indices(index_values, 3, &[0, 15]));
+
+        let decoded = decode_bc6h_block(&pack(&fields), false);
+        let w = half([0x307f, 0x3c9b, 0x48b7]);
+        let y = half([0x2f49, 0x3e6c, 0x48d6]);
+        assert_eq!(decoded[0], w);
+        assert_eq!(decoded[1], half([0x30dc, 0x3c5d, 0x48b7]));
+        assert_eq!(decoded[2..8], [w; 6]);
+        assert_eq!(decoded[8..14], [y; 6]);
+        assert_eq!(decoded[14], half([0x311a, 0x3d36, 0x46c7]));
+        assert_eq!(decoded[15], y);
+    }
+
+    #[test]
+    fn bc6h_reserved_mode() {
+        // 0b10011 isn't a mode
+        let mut block = [0u8; 16];
+        block[0] = 0b10011;
+        block[1] = 0xff;
+        assert_eq!(decode_bc6h_block(&block, false), [[0.0; 3]; 16]);
+    }
+}
@@ -0,0 +1,578 @@
+//! Flies the camera along a fixed path once the scene is loaded and writes a report of the
+//! frame times, so performance can be compared between runs and Bevy versions.
+
+use std::{
+    collections::VecDeque,
+    fs,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
+
+use anyhow::{anyhow, Context};
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    render::{
+        main_graph::node::CAMERA_DRIVER,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{Buffer, BufferDescriptor, BufferUsages, MapMode, WgpuFeatures},
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        view::VisibleEntities,
+        RenderApp, RenderStage,
+    },
+    window::PresentMode,
+};
+use serde::Serialize;
+
+use crate::{camera_controller::CameraController, loading::AppState};
+
+const GPU_TIMER_BEGIN: &str = "bench_gpu_timer_begin";
+const GPU_TIMER_END: &str = "bench_gpu_timer_end";
+/// Readback buffers for the GPU timestamps, so they can be read a few frames later.
+const GPU_TIMER_READBACKS: usize = 4;
+
+/// Frames rendered once everything is loaded before measuring.
+const WARMUP_FRAMES: u32 = 30;
+
+/// Camera position and the point it looks at, the camera goes through all of them.
+const CAMERA_PATH: [([f32; 3], [f32; 3]); 7] = [
+    ([-10.5, 1.7, -1.0], [0.0, 3.5, 0.0]),
+    ([-4.0, 1.7, 0.0], [4.0, 2.5, 0.0]),
+    ([4.0, 1.7, 0.5], [10.0, 3.0, 0.0]),
+    ([9.0, 4.0, 2.0], [0.0, 3.0, 0.0]),
+    ([0.0, 8.0, 3.0], [0.0, 2.0, -3.0]),
+    ([-9.0, 6.0, -2.0], [10.0, 4.0, 0.0]),
+    ([-10.5, 1.7, -1.0], [0.0, 3.5, 0.0]),
+];
+
+#[derive(Resource, Clone)]
+pub struct BenchSettings {
+    /// Number of frames to render along the path, the camera moves the same amount each frame.
+    pub frames: u32,
+    /// `.csv` or `.json`.
+    pub report: PathBuf,
+    /// Gives up if the scene isn't loaded after this many seconds.
+    pub timeout: f32,
+}
+
+/// Set once the app exits, the bench command fails if it isn't `Ok`.
+#[derive(Resource)]
+pub struct BenchResult(pub anyhow::Result<()>);
+
+pub struct BenchPlugin;
+
+impl Plugin for BenchPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<BenchState>()
+            .add_startup_system(disable_vsync)
+            .add_system_to_stage(CoreStage::First, start_frame_timer)
+            .add_system(fly_camera)
+            .add_system_to_stage(CoreStage::Last, record_frame);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        let render_device = render_app.world.resource::<RenderDevice>().clone();
+        if !render_device
+            .features()
+            .contains(WgpuFeatures::TIMESTAMP_QUERY)
+        {
+            warn!("The GPU doesn't support timestamp queries, the report won't have GPU times");
+            return;
+        }
+        let period = render_app
+            .world
+            .resource::<RenderQueue>()
+            .get_timestamp_period();
+        let (sender, receiver) = channel();
+        render_app
+            .insert_resource(GpuTimer::new(&render_device, period, sender))
+            .add_system_to_stage(RenderStage::Prepare, select_gpu_timer_readback)
+            .add_system_to_stage(RenderStage::Cleanup, read_gpu_timer);
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(GPU_TIMER_BEGIN, GpuTimestampNode(0));
+        graph.add_node(GPU_TIMER_END, GpuTimestampNode(1));
+        graph.add_node_edge(GPU_TIMER_BEGIN, CAMERA_DRIVER).unwrap();
+        graph.add_node_edge(CAMERA_DRIVER, GPU_TIMER_END).unwrap();
+        app.insert_resource(GpuTimeReceiver(Mutex::new(receiver)));
+    }
+}
+
+#[derive(Default, PartialEq, Eq)]
+enum BenchPhase {
+    #[default]
+    Loading,
+    Warmup(u32),
+    Running,
+}
+
+#[derive(Resource, Default)]
+struct BenchState {
+    phase: BenchPhase,
+    /// Frames rendered since the start of the run.
+    frame: u32,
+    frame_start: Option<Instant>,
+    frames: Vec<FrameSample>,
+}
+
+#[derive(Serialize, Clone, Copy)]
+struct FrameSample {
+    frame_ms: f32,
+    main_update_ms: f32,
+    /// Time the GPU spent rendering one of the last few frames, the newest one read back.
+    gpu_ms: Option<f32>,
+    entities: u32,
+    /// Entities visible to the camera, roughly the number of draws of the main pass.
+    visible_entities: u32,
+}
+
+fn disable_vsync(mut windows: ResMut<Windows>) {
+    if let Some(window) = windows.get_primary_mut() {
+        window.set_present_mode(PresentMode::AutoNoVsync);
+    }
+}
+
+fn start_frame_timer(mut state: ResMut<BenchState>) {
+    state.frame_start = Some(Instant::now());
+}
+
+fn fly_camera(
+    time: Res<Time>,
+    settings: Res<BenchSettings>,
+    app_state: Res<State<AppState>>,
+    mut state: ResMut<BenchState>,
+    mut cameras: Query<(&mut Transform, &mut CameraController), With<Camera3d>>,
+    mut commands: Commands,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    match state.phase {
+        BenchPhase::Loading => {
+            if *app_state.current() == AppState::Ready {
+                state.phase = BenchPhase::Warmup(0);
+            } else if time.elapsed_seconds() > settings.timeout {
+                commands.insert_resource(BenchResult(Err(anyhow!(
+                    "The scene didn't finish loading after {} seconds",
+                    settings.timeout
+                ))));
+                app_exit.send(AppExit);
+            }
+        }
+        BenchPhase::Warmup(_) | BenchPhase::Running if *app_state.current() != AppState::Ready => {
+            println!("The scene is loading again, restarting the benchmark");
+            state.phase = BenchPhase::Loading;
+            state.frame = 0;
+            state.frames.clear();
+        }
+        BenchPhase::Warmup(frames) => {
+            state.phase = if frames >= WARMUP_FRAMES {
+                println!("Running the benchmark for {} frames", settings.frames);
+                BenchPhase::Running
+            } else {
+                BenchPhase::Warmup(frames + 1)
+            };
+        }
+        // The first frame of the run is the one switching to running, it's recorded as frame 0
+        BenchPhase::Running => {
+            state.frame += 1;
+            if state.frame >= settings.frames {
+                let result = write_report(&settings, &state.frames);
+                if result.is_ok() {
+                    println!("Saved {}", settings.report.display());
+                }
+                commands.insert_resource(BenchResult(result));
+                app_exit.send(AppExit);
+            }
+        }
+    }
+
+    // The last recorded frame is at the end of the path
+    let t = match state.phase {
+        BenchPhase::Running => state.frame as f32 / (settings.frames - 1).max(1) as f32,
+        _ => 0.0,
+    };
+    for (mut transform, mut controller) in &mut cameras {
+        controller.enabled = false;
+        *transform = camera_path(t);
+    }
+}
+
+/// Catmull-Rom spline through `CAMERA_PATH`, `t` goes from 0 to 1.
+fn camera_path(t: f32) -> Transform {
+    let segments = CAMERA_PATH.len() - 1;
+    let position = t * segments as f32;
+    let segment = (position as usize).min(segments - 1);
+    let local_t = position - segment as f32;
+    let point = |i: isize| {
+        let i = i.clamp(0, segments as isize) as usize;
+        let (position, target) = CAMERA_PATH[i];
+        (Vec3::from(position), Vec3::from(target))
+    };
+    let i = segment as isize;
+    let (p0, t0) = point(i - 1);
+    let (p1, t1) = point(i);
+    let (p2, t2) = point(i + 1);
+    let (p3, t3) = point(i + 2);
+    let position = catmull_rom(p0, p1, p2, p3, local_t);
+    let target = catmull_rom(t0, t1, t2, t3, local_t);
+    Transform::from_translation(position).looking_at(target, Vec3::Y)
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+fn record_frame(
+    time: Res<Time>,
+    mut state: ResMut<BenchState>,
+    entities: Query<Entity>,
+    views: Query<(&Camera, &VisibleEntities), With<Camera3d>>,
+    gpu_time: Option<Res<GpuTimeReceiver>>,
+) {
+    // Drain the channel every frame so old GPU times don't get attributed to the run
+    let gpu_ms = gpu_time.and_then(|receiver| receiver.0.lock().unwrap().try_iter().last());
+    if state.phase != BenchPhase::Running {
+        return;
+    }
+    let main_update_ms = state
+        .frame_start
+        .map_or(0.0, |start| start.elapsed().as_secs_f32() * 1000.0);
+    let sample = FrameSample {
+        frame_ms: time.delta_seconds() * 1000.0,
+        main_update_ms,
+        gpu_ms,
+        entities: entities.iter().count() as u32,
+        visible_entities: views
+            .iter()
+            .filter(|(camera, _)| camera.is_active)
+            .map(|(_, visible)| visible.entities.len() as u32)
+            .sum(),
+    };
+    state.frames.push(sample);
+}
+
+#[derive(Serialize)]
+struct Stats {
+    min: f32,
+    avg: f32,
+    p95: f32,
+    p99: f32,
+    max: f32,
+}
+
+impl Stats {
+    fn new(mut values: Vec<f32>) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f32::total_cmp);
+        let percentile = |p: f32| {
+            let rank = (p / 100.0 * values.len() as f32).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Some(Self {
+            min: values[0],
+            avg: values.iter().sum::<f32>() / values.len() as f32,
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: values[values.len() - 1],
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    frames: usize,
+    /// Sum of the frame times, in seconds.
+    duration: f32,
+    frame_ms: Option<Stats>,
+    main_update_ms: Option<Stats>,
+    gpu_ms: Option<Stats>,
+    entities: Option<Stats>,
+    visible_entities: Option<Stats>,
+    samples: &'a [FrameSample],
+}
+
+fn write_report(settings: &BenchSettings, frames: &[FrameSample]) -> anyhow::Result<()> {
+    let stats = |value: fn(&FrameSample) -> Option<f32>| {
+        Stats::new(frames.iter().filter_map(value).collect())
+    };
+    let report = Report {
+        frames: frames.len(),
+        duration: frames.iter().map(|frame| frame.frame_ms).sum::<f32>() / 1000.0,
+        frame_ms: stats(|frame| Some(frame.frame_ms)),
+        main_update_ms: stats(|frame| Some(frame.main_update_ms)),
+        gpu_ms: stats(|frame| frame.gpu_ms),
+        entities: stats(|frame| Some(frame.entities as f32)),
+        visible_entities: stats(|frame| Some(frame.visible_entities as f32)),
+        samples: frames,
+    };
+
+    let is_csv = settings
+        .report
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("csv"));
+    let contents = if is_csv {
+        // Only the summary, one metric per row
+        let mut csv = String::from("metric,min,avg,p95,p99,max\n");
+        for (name, stats) in [
+            ("frame_ms", &report.frame_ms),
+            ("main_update_ms", &report.main_update_ms),
+            ("gpu_ms", &report.gpu_ms),
+            ("entities", &report.entities),
+            ("visible_entities", &report.visible_entities),
+        ] {
+            if let Some(s) = stats {
+                csv += &format!("{name},{},{},{},{},{}\n", s.min, s.avg, s.p95, s.p99, s.max);
+            }
+        }
+        csv
+    } else {
+        serde_json::to_string_pretty(&report)?
+    };
+    fs::write(&settings.report, contents)
+        .with_context(|| format!("Failed to write {}", settings.report.display()))
+}
+
+#[derive(Resource)]
+struct GpuTimeReceiver(Mutex<Receiver<f32>>);
+
+/// Timestamps written before and after the camera driver, in the render world.
+#[derive(Resource)]
+struct GpuTimer {
+    query_set: wgpu::QuerySet,
+    resolve: Buffer,
+    readbacks: Vec<Buffer>,
+    /// Readback buffers being mapped, oldest first, the flag is set once the mapping is done.
+    pending: VecDeque<(usize, Arc<Mutex<Option<bool>>>)>,
+    /// Readback buffer the timestamps of this frame are copied to, `None` if they are all busy.
+    current: Option<usize>,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    sender: Sender<f32>,
+}
+
+impl GpuTimer {
+    fn new(render_device: &RenderDevice, period: f32, sender: Sender<f32>) -> Self {
+        let query_set = render_device
+            .wgpu_device()
+            .create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("bench_gpu_timer"),
+                ty: wgpu::QueryType::Timestamp,
+                count: 2,
+            });
+        let size = 2 * std::mem::size_of::<u64>() as u64;
+        let resolve = render_device.create_buffer(&BufferDescriptor {
+            label: Some("bench_gpu_timer_resolve"),
+            size,
+            usage: BufferUsages::COPY_DST | BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readbacks = (0..GPU_TIMER_READBACKS)
+            .map(|_| {
+                render_device.create_buffer(&BufferDescriptor {
+                    label: Some("bench_gpu_timer_readback"),
+                    size,
+                    usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+                    mapped_at_creation: false,
+                })
+            })
+            .collect();
+        Self {
+            query_set,
+            resolve,
+            readbacks,
+            pending: VecDeque::new(),
+            current: None,
+            period,
+            sender,
+        }
+    }
+}
+
+/// Writes timestamp `0` or `1`, the end node also copies them to the current readback buffer.
+struct GpuTimestampNode(u32);
+
+impl Node for GpuTimestampNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let timer = world.resource::<GpuTimer>();
+        let encoder = &mut render_context.command_encoder;
+        encoder.write_timestamp(&timer.query_set, self.0);
+        if self.0 == 1 {
+            encoder.resolve_query_set(&timer.query_set, 0..2, &timer.resolve, 0);
+            if let Some(index) = timer.current {
+                encoder.copy_buffer_to_buffer(
+                    &timer.resolve,
+                    0,
+                    &timer.readbacks[index],
+                    0,
+                    timer.resolve.size(),
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Picks a readback buffer that isn't waiting to be mapped. When the GPU is more than
+/// `GPU_TIMER_READBACKS` frames behind the frame isn't timed.
+fn select_gpu_timer_readback(mut timer: ResMut<GpuTimer>) {
+    timer.current = (0..timer.readbacks.len())
+        .find(|index| timer.pending.iter().all(|(pending, _)| pending != index));
+}
+
+/// Runs after the frame was submitted. The readback buffer is only mapped once the GPU is
+/// done with it, so the CPU never waits for the GPU and frames can overlap like in the viewer.
+fn read_gpu_timer(mut timer: ResMut<GpuTimer>, render_device: Res<RenderDevice>) {
+    let timer = &mut *timer;
+    if let Some(index) = timer.current.take() {
+        let mapped = Arc::new(Mutex::new(None));
+        let callback_mapped = mapped.clone();
+        timer.readbacks[index]
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                *callback_mapped.lock().unwrap() = Some(result.is_ok());
+            });
+        timer.pending.push_back((index, mapped));
+    }
+    render_device.poll(wgpu::Maintain::Poll);
+
+    // Oldest first so the times arrive in frame order
+    while let Some((index, mapped)) = timer.pending.front() {
+        let Some(ok) = *mapped.lock().unwrap() else {
+            break;
+        };
+        let readback = &timer.readbacks[*index];
+        if ok {
+            let data = readback.slice(..).get_mapped_range();
+            let begin = u64::from_le_bytes(data[0..8].try_into().unwrap());
+            let end = u64::from_le_bytes(data[8..16].try_into().unwrap());
+            drop(data);
+            readback.unmap();
+            let ms = end.saturating_sub(begin) as f32 * timer.period / 1_000_000.0;
+            let _ = timer.sender.send(ms);
+        }
+        timer.pending.pop_front();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_percentiles() {
+        assert!(Stats::new(Vec::new()).is_none());
+
+        let single = Stats::new(vec![4.0]).unwrap();
+        assert_eq!(
+            (single.min, single.avg, single.p95, single.p99, single.max),
+            (4.0, 4.0, 4.0, 4.0, 4.0)
+        );
+
+        // 1 to 100 shuffled, the nearest rank percentile of p is p
+        let values = (0..100).map(|i| ((i * 37) % 100 + 1) as f32).collect();
+        let stats = Stats::new(values).unwrap();
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.avg, 50.5);
+        assert_eq!(stats.p95, 95.0);
+        assert_eq!(stats.p99, 99.0);
+        assert_eq!(stats.max, 100.0);
+
+        // With 10 values both percentiles round up to the largest one
+        let stats = Stats::new((1..=10).map(|i| i as f32).collect()).unwrap();
+        assert_eq!(stats.p95, 10.0);
+        assert_eq!(stats.p99, 10.0);
+    }
+
+    #[test]
+    fn report_has_one_sample_per_frame() {
+        let report = std::env::temp_dir().join(format!("sponza_bench_{}.json", std::process::id()));
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_event::<AppExit>()
+            .insert_resource(State::new(AppState::Ready))
+            .insert_resource(BenchSettings {
+                frames: 20,
+                report: report.clone(),
+                timeout: 10.0,
+            })
+            .init_resource::<BenchState>()
+            .add_system(fly_camera)
+            .add_system_to_stage(CoreStage::Last, record_frame);
+        for _ in 0..100 {
+            if app.world.contains_resource::<BenchResult>() {
+                break;
+            }
+            app.update();
+        }
+        app.world
+            .remove_resource::<BenchResult>()
+            .unwrap()
+            .0
+            .unwrap();
+
+        let contents = fs::read_to_string(&report).unwrap();
+        fs::remove_file(&report).unwrap();
+        let report: serde_json::Value = serde_json::from_str(&contents).unwrap();
+        assert_eq!(report["frames"], 20);
+        assert_eq!(report["samples"].as_array().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn catmull_rom_goes_through_the_inner_points() {
+        let p = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 2.0, 0.0),
+            Vec3::new(3.0, 2.0, 1.0),
+            Vec3::new(4.0, 0.0, 1.0),
+        ];
+        assert!(catmull_rom(p[0], p[1], p[2], p[3], 0.0).abs_diff_eq(p[1], 1e-6));
+        assert!(catmull_rom(p[0], p[1], p[2], p[3], 1.0).abs_diff_eq(p[2], 1e-6));
+
+        // Evenly spaced points on a line are followed at constant speed
+        let line = |i: f32| Vec3::new(i, 2.0 * i, -i);
+        let mid = catmull_rom(line(0.0), line(1.0), line(2.0), line(3.0), 0.25);
+        assert!(mid.abs_diff_eq(line(1.25), 1e-6));
+    }
+
+    #[test]
+    fn camera_path_hits_every_point() {
+        let segments = CAMERA_PATH.len() - 1;
+        for (i, (position, target)) in CAMERA_PATH.iter().enumerate() {
+            let transform = camera_path(i as f32 / segments as f32);
+            let position = Vec3::from(*position);
+            assert!(
+                transform.translation.abs_diff_eq(position, 1e-4),
+                "point {i}"
+            );
+            let forward = transform.forward();
+            let expected = (Vec3::from(*target) - position).normalize();
+            assert!(forward.abs_diff_eq(expected, 1e-4), "point {i}");
+        }
+    }
+
+    #[test]
+    fn camera_path_is_continuous() {
+        let steps = 1000;
+        let mut previous = camera_path(0.0).translation;
+        for step in 1..=steps {
+            let position = camera_path(step as f32 / steps as f32).translation;
+            assert!(previous.distance(position) < 0.2, "step {step}");
+            previous = position;
+        }
+    }
+}
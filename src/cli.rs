@@ -0,0 +1,366 @@
+use std::{path::PathBuf, str::FromStr, thread::available_parallelism};
+
+use anyhow::anyhow;
+use bevy::{
+    prelude::{Transform, Vec3},
+    render::settings::{Backends, PowerPreference, WgpuSettings, WgpuSettingsPriority},
+};
+use clap::{Args, Parser, Subcommand};
+
+use crate::{
+    bcn::BlockFormat,
+    bench::BenchSettings,
+    convert::{ConvertSettings, EncoderBackend},
+    scene_manifest::SceneManifest,
+    scene_objects::LightUnits,
+    screenshot::ScreenshotSettings,
+};
+
+const DEFAULT_ASSET_ROOT: &str = "assets";
+const DEFAULT_SCENE: &str = "scenes/sponza.ron";
+const DEFAULT_LIGHTING: &str = "grif";
+const DEFAULT_WIDTH: f32 = 1280.0;
+const DEFAULT_HEIGHT: f32 = 720.0;
+
+#[derive(Parser)]
+#[command(about = "Intel's New Sponza in Bevy", long_about = None)]
+pub struct Cli {
+    /// Defaults to `view`
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Open the scene in a window
+    View(ViewArgs),
+    /// Convert the png textures to KTX2 and point the gltf files at them
+    Convert(ConvertArgs),
+    /// Point the gltf files back at the png textures
+    Revert(RevertArgs),
+    /// Run the viewer for a fixed number of frames, logging frame times
+    Bench(BenchArgs),
+    /// Render a single frame to an image file
+    Screenshot(ScreenshotArgs),
+    /// Check that the scene files are all there
+    Validate(ValidateArgs),
+    /// List the materials of the scene with their factors, textures and matching overrides
+    Materials(MaterialsArgs),
+    /// Load the scene without a window and print mesh, material and texture statistics
+    Stats(StatsArgs),
+}
+
+impl Default for Command {
+    fn default() -> Self {
+        Command::View(ViewArgs::default())
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct AssetArgs {
+    /// Folder containing the scene packages
+    #[arg(long, default_value = DEFAULT_ASSET_ROOT)]
+    pub asset_root: PathBuf,
+    /// Scene manifest listing the packages to load
+    #[arg(long, default_value = DEFAULT_SCENE)]
+    pub scene: PathBuf,
+}
+
+impl Default for AssetArgs {
+    fn default() -> Self {
+        Self {
+            asset_root: DEFAULT_ASSET_ROOT.into(),
+            scene: DEFAULT_SCENE.into(),
+        }
+    }
+}
+
+impl AssetArgs {
+    pub fn load_scene(&self) -> anyhow::Result<SceneManifest> {
+        Ok(SceneManifest::load(&self.scene)?.resolve(&self.asset_root))
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct WindowArgs {
+    #[arg(long, default_value_t = DEFAULT_WIDTH)]
+    pub width: f32,
+    #[arg(long, default_value_t = DEFAULT_HEIGHT)]
+    pub height: f32,
+}
+
+impl Default for WindowArgs {
+    fn default() -> Self {
+        Self {
+            width: DEFAULT_WIDTH,
+            height: DEFAULT_HEIGHT,
+        }
+    }
+}
+
+#[derive(Args, Clone)]
+pub struct ViewArgs {
+    #[command(flatten)]
+    pub assets: AssetArgs,
+    #[command(flatten)]
+    pub window: WindowArgs,
+    /// Start with the sun at this time of day, in hours
+    #[arg(long)]
+    pub hour: Option<f32>,
+    /// Lighting preset to start with, the name of a file in `<asset root>/lighting` without `.lighting.ron`
+    #[arg(long, default_value = DEFAULT_LIGHTING)]
+    pub lighting: String,
+    /// Keep the lights of the gltf files and start with them instead of the lighting preset
+    #[arg(long)]
+    pub gltf_lights: bool,
+    /// `candela` or `watts`, the unit of the gltf light intensities
+    #[arg(long, default_value = "candela")]
+    pub gltf_light_units: LightUnits,
+    /// Keep the cameras of the gltf files as viewpoints
+    #[arg(long)]
+    pub gltf_cameras: bool,
+    /// `vulkan`, `gl`, `dx12` or `metal`, overrides `WGPU_BACKEND`
+    #[arg(long)]
+    pub backend: Option<RenderBackend>,
+    /// Settle for a software adapter like lavapipe, for machines without a GPU
+    #[arg(long)]
+    pub fallback_adapter: bool,
+}
+
+impl Default for ViewArgs {
+    fn default() -> Self {
+        Self {
+            assets: AssetArgs::default(),
+            window: WindowArgs::default(),
+            hour: None,
+            lighting: DEFAULT_LIGHTING.into(),
+            gltf_lights: false,
+            gltf_light_units: LightUnits::Candela,
+            gltf_cameras: false,
+            backend: None,
+            fallback_adapter: false,
+        }
+    }
+}
+
+impl ViewArgs {
+    pub fn wgpu_settings(&self) -> WgpuSettings {
+        let mut settings = WgpuSettings::default();
+        if let Some(backend) = self.backend {
+            settings.backends = Some(backend.backends());
+        }
+        if self.fallback_adapter {
+            // Bevy doesn't pass wgpu's `force_fallback_adapter` on. Software adapters are only
+            // picked when there's nothing else, and only guarantee the WebGPU default limits.
+            if self.backend.is_none() {
+                settings.backends = Some(Backends::VULKAN);
+            }
+            settings.power_preference = PowerPreference::LowPower;
+            settings.priority = WgpuSettingsPriority::Compatibility;
+        }
+        settings
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderBackend {
+    Vulkan,
+    Gl,
+    Dx12,
+    Metal,
+}
+
+impl RenderBackend {
+    fn backends(self) -> Backends {
+        match self {
+            RenderBackend::Vulkan => Backends::VULKAN,
+            RenderBackend::Gl => Backends::GL,
+            RenderBackend::Dx12 => Backends::DX12,
+            RenderBackend::Metal => Backends::METAL,
+        }
+    }
+}
+
+impl FromStr for RenderBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "vulkan" => Ok(RenderBackend::Vulkan),
+            "gl" => Ok(RenderBackend::Gl),
+            "dx12" => Ok(RenderBackend::Dx12),
+            "metal" => Ok(RenderBackend::Metal),
+            _ => Err(anyhow!(
+                "Unknown backend {s}, expected vulkan, gl, dx12 or metal"
+            )),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct ConvertArgs {
+    #[command(flatten)]
+    pub assets: AssetArgs,
+    /// `native` or `kram`, kram needs to be in your path
+    #[arg(long, default_value = "native")]
+    pub encoder: EncoderBackend,
+    /// `bc4`, `bc5` or `bc7`
+    #[arg(long, default_value = "bc7")]
+    pub color_format: BlockFormat,
+    /// `bc4`, `bc5` or `bc7`
+    #[arg(long, default_value = "bc7")]
+    pub normal_format: BlockFormat,
+    /// `bc4`, `bc5` or `bc7`
+    #[arg(long, default_value = "bc7")]
+    pub metal_rough_format: BlockFormat,
+    /// zstd supercompression level, 0 uses zstd's default level
+    #[arg(long, default_value_t = 0, conflicts_with = "no_zstd")]
+    pub zstd: i32,
+    /// Disable zstd supercompression
+    #[arg(long)]
+    pub no_zstd: bool,
+    /// Re-encode every texture, even the ones the manifest says are up to date
+    #[arg(long)]
+    pub force: bool,
+    /// Reference the KTX2 files through the SPONZA_texture_ktx2 extension instead of replacing the png uris
+    #[arg(long)]
+    pub ktx2_extension: bool,
+    /// Number of textures encoded in parallel, defaults to the available parallelism
+    #[arg(long)]
+    pub threads: Option<usize>,
+}
+
+impl ConvertArgs {
+    pub fn settings(&self) -> ConvertSettings {
+        ConvertSettings {
+            backend: self.encoder,
+            color_format: self.color_format,
+            normal_format: self.normal_format,
+            metallic_roughness_format: self.metal_rough_format,
+            zstd_level: (!self.no_zstd).then_some(self.zstd),
+            force: self.force,
+            ktx2_extension: self.ktx2_extension,
+            threads: self
+                .threads
+                .unwrap_or_else(|| available_parallelism().map_or(1, |n| n.get()))
+                .max(1),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct RevertArgs {
+    #[command(flatten)]
+    pub assets: AssetArgs,
+    /// Also delete the converted KTX2 files
+    #[arg(long)]
+    pub delete_ktx2: bool,
+}
+
+#[derive(Args)]
+pub struct BenchArgs {
+    #[command(flatten)]
+    pub view: ViewArgs,
+    /// Number of frames to render along the camera path, the same frames are rendered every run
+    #[arg(long, default_value_t = 1800, value_parser = clap::value_parser!(u32).range(1..))]
+    pub frames: u32,
+    /// Where to write the report, `.json` has every frame and `.csv` only the summary
+    #[arg(long, default_value = "bench_report.json")]
+    pub report: PathBuf,
+    /// Fail if the scene takes longer than this to load, in seconds
+    #[arg(long, default_value_t = 600.0)]
+    pub timeout: f32,
+}
+
+impl BenchArgs {
+    pub fn settings(&self) -> anyhow::Result<BenchSettings> {
+        let extension = self
+            .report
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        if !matches!(extension.as_deref(), Some("csv" | "json")) {
+            return Err(anyhow!(
+                "Bench reports can only be saved as csv or json, got {}",
+                self.report.display()
+            ));
+        }
+        Ok(BenchSettings {
+            frames: self.frames,
+            report: self.report.clone(),
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct ScreenshotArgs {
+    #[command(flatten)]
+    pub view: ViewArgs,
+    /// Where to write the image, `.png` is tonemapped and `.exr` keeps the HDR values
+    #[arg(long, short, default_value = "screenshot.png")]
+    pub output: PathBuf,
+    /// Camera position, defaults to the viewer's starting point
+    #[arg(long, num_args = 3, value_names = ["X", "Y", "Z"], allow_negative_numbers = true, requires = "look_at")]
+    pub position: Option<Vec<f32>>,
+    /// Point the camera looks at
+    #[arg(long, num_args = 3, value_names = ["X", "Y", "Z"], allow_negative_numbers = true, requires = "position")]
+    pub look_at: Option<Vec<f32>>,
+    /// Vertical field of view, in degrees
+    #[arg(long)]
+    pub fov: Option<f32>,
+    /// Fail if the scene takes longer than this to load, in seconds
+    #[arg(long, default_value_t = 600.0)]
+    pub timeout: f32,
+}
+
+impl ScreenshotArgs {
+    pub fn settings(&self) -> anyhow::Result<ScreenshotSettings> {
+        let extension = self
+            .output
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        if !matches!(extension.as_deref(), Some("png" | "exr")) {
+            return Err(anyhow!(
+                "Screenshots can only be saved as png or exr, got {}",
+                self.output.display()
+            ));
+        }
+        let camera = match (&self.position, &self.look_at) {
+            (Some(position), Some(look_at)) => Some(
+                Transform::from_translation(Vec3::from_slice(position))
+                    .looking_at(Vec3::from_slice(look_at), Vec3::Y),
+            ),
+            _ => None,
+        };
+        Ok(ScreenshotSettings {
+            output: self.output.clone(),
+            width: self.view.window.width as u32,
+            height: self.view.window.height as u32,
+            camera,
+            fov: self.fov,
+            timeout: self.timeout,
+        })
+    }
+}
+
+#[derive(Args)]
+pub struct ValidateArgs {
+    #[command(flatten)]
+    pub assets: AssetArgs,
+}
+
+#[derive(Args)]
+pub struct MaterialsArgs {
+    #[command(flatten)]
+    pub assets: AssetArgs,
+}
+
+#[derive(Args)]
+pub struct StatsArgs {
+    #[command(flatten)]
+    pub view: ViewArgs,
+    /// Fail if the scene takes longer than this to load, in seconds
+    #[arg(long, default_value_t = 600.0)]
+    pub timeout: f32,
+}
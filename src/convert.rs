@@ -1,71 +1,604 @@
+use anyhow::{anyhow, Context};
+use image::imageops::FilterType;
 use threadpool::ThreadPool;
 
-use std::{fs, io::Write, process::Command, thread::available_parallelism};
-
-pub fn change_gltf_to_use_ktx2() {
-    for path in [
-        "./assets/main_sponza/NewSponza_Main_glTF_002.gltf",
-        "./assets/PKG_A_Curtains/NewSponza_Curtains_glTF.gltf",
-    ] {
-        let contents = fs::read_to_string(path).unwrap();
-        let new = contents.replace(".png", ".ktx2");
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path)
-            .unwrap();
-        let _ = file.write(new.as_bytes()).unwrap();
-    }
-}
-
-pub fn convert_images_to_ktx2() {
-    for path in [
-        "./assets/PKG_A_Curtains/textures",
-        "./assets/main_sponza/textures",
-    ] {
-        let pool = ThreadPool::new(available_parallelism().unwrap().get());
-        for path in fs::read_dir(path).unwrap() {
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread::available_parallelism,
+};
+
+use crate::{
+    bcn::{self, BlockFormat},
+    gltf_json::{replace_uri_extension, GltfDocument, KHR_TEXTURE_BASISU, SPONZA_TEXTURE_KTX2},
+    ktx2_writer::Ktx2Texture,
+    scene_manifest::SceneManifest,
+    texture_manifest::{hash_file, ManifestEntry, TextureManifest},
+};
+
+/// Points the glTF images at the converted KTX2 files.
+/// Only images with a `.ktx2` next to them are changed, the original file is kept as `<name>.gltf.bak`.
+pub fn change_gltf_to_use_ktx2(
+    asset_root: &Path,
+    scene: &SceneManifest,
+    settings: &ConvertSettings,
+) -> anyhow::Result<()> {
+    let mut failed = 0;
+    for package in &scene.packages {
+        if let Err(e) = rewrite_gltf(&package.gltf_path(asset_root), settings) {
+            eprintln!("{e:#}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("Failed to update {failed} gltf files"));
+    }
+    Ok(())
+}
+
+fn rewrite_gltf(path: &Path, settings: &ConvertSettings) -> anyhow::Result<()> {
+    let mut gltf = GltfDocument::load(path)?;
+    let uris = gltf.image_uris();
+
+    // Only back up files that don't reference any KTX2 yet, so the backup is always the original
+    if !uris.iter().any(|(_, uri)| has_extension(uri, "ktx2")) {
+        let backup_path = GltfDocument::backup_path(path);
+        fs::copy(path, &backup_path)
+            .with_context(|| format!("Failed to write {}", backup_path.display()))?;
+    }
+
+    let mut converted = Vec::new();
+    let mut kept = 0;
+    for (index, uri) in uris {
+        if !has_extension(&uri, "png") {
+            continue;
+        }
+        if gltf.dir().join(&uri).with_extension("ktx2").is_file() {
+            converted.push(index);
+        } else {
+            kept += 1;
+        }
+    }
+
+    let changed = if settings.ktx2_extension {
+        add_ktx2_sources(&mut gltf, &converted)?
+    } else if let Some(images) = gltf.images_mut() {
+        for &index in &converted {
+            let image = images[index].as_object_mut().unwrap();
+            let uri = replace_uri_extension(image["uri"].as_str().unwrap(), "ktx2");
+            image.insert("uri".into(), uri.into());
+            if image.contains_key("mimeType") {
+                image.insert("mimeType".into(), KTX2_MIME_TYPE.into());
+            }
+        }
+        converted.len()
+    } else {
+        0
+    };
+
+    gltf.save()?;
+    println!(
+        "{}: {changed} images now use KTX2, {kept} kept as png",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Adds the KTX2 images as `SPONZA_texture_ktx2` sources, keeping the pngs as the fallback `source`.
+/// The extension isn't required, so loaders that don't know it keep using the pngs.
+fn add_ktx2_sources(gltf: &mut GltfDocument, converted: &[usize]) -> anyhow::Result<usize> {
+    let Some(textures) = gltf.json.get("textures").and_then(|t| t.as_array()) else {
+        return Ok(0);
+    };
+
+    // (texture index, png image index) of every texture that still needs the extension
+    let pending: Vec<(usize, usize)> = textures
+        .iter()
+        .enumerate()
+        .filter(|(_, texture)| texture.pointer("/extensions/SPONZA_texture_ktx2").is_none())
+        .filter_map(|(i, texture)| Some((i, texture.get("source")?.as_u64()? as usize)))
+        .filter(|(_, source)| converted.contains(source))
+        .collect();
+    if pending.is_empty() {
+        return Ok(0);
+    }
+
+    let images = gltf.images_mut().unwrap();
+    let mut ktx2_images = HashMap::new();
+    for &(_, source) in &pending {
+        ktx2_images.entry(source).or_insert_with(|| {
+            let uri = replace_uri_extension(images[source]["uri"].as_str().unwrap(), "ktx2");
+            images.push(json!({ "uri": uri, "mimeType": KTX2_MIME_TYPE }));
+            images.len() - 1
+        });
+    }
+
+    let textures = gltf.json["textures"].as_array_mut().unwrap();
+    for &(texture, source) in &pending {
+        let texture = textures[texture].as_object_mut().unwrap();
+        let extensions = texture.entry("extensions").or_insert_with(|| json!({}));
+        extensions[SPONZA_TEXTURE_KTX2] = json!({ "source": ktx2_images[&source] });
+    }
+    gltf.add_extension_used(SPONZA_TEXTURE_KTX2)?;
+
+    Ok(ktx2_images.len())
+}
+
+/// Restores the glTF files to the png textures, undoing `change_gltf_to_use_ktx2`.
+/// Uses the `.gltf.bak` backup when there is one, otherwise maps the KTX2 uris back with the manifests.
+pub fn revert_gltf_to_png(
+    asset_root: &Path,
+    scene: &SceneManifest,
+    delete_ktx2: bool,
+) -> anyhow::Result<()> {
+    let textures_dirs = textures_dirs(asset_root, scene);
+    let mut failed = 0;
+    for package in &scene.packages {
+        if let Err(e) = revert_gltf(&package.gltf_path(asset_root), &textures_dirs) {
+            eprintln!("{e:#}");
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!("Failed to revert {failed} gltf files"));
+    }
+
+    if delete_ktx2 {
+        for textures_dir in &textures_dirs {
+            let manifest = TextureManifest::load(textures_dir);
+            let mut deleted = 0;
+            for entry in manifest.entries.values() {
+                if fs::remove_file(textures_dir.join(&entry.output)).is_ok() {
+                    deleted += 1;
+                }
+            }
+            let _ = fs::remove_file(TextureManifest::path(textures_dir));
+            println!("{}: deleted {deleted} KTX2 files", textures_dir.display());
+        }
+    }
+    Ok(())
+}
+
+fn revert_gltf(path: &Path, textures_dirs: &[PathBuf]) -> anyhow::Result<()> {
+    // Checked before parsing so a broken converted file can still be restored
+    let backup_path = GltfDocument::backup_path(path);
+    if backup_path.is_file() {
+        fs::rename(&backup_path, path)
+            .with_context(|| format!("Failed to restore {}", backup_path.display()))?;
+        println!("{}: restored from backup", path.display());
+        return Ok(());
+    }
+    let mut gltf = GltfDocument::load(path)?;
+
+    // Maps "<textures dir>/<output>" back to the source it was made from
+    let mut sources = HashMap::new();
+    for textures_dir in textures_dirs {
+        for (source, entry) in TextureManifest::load(textures_dir).entries {
+            let output = textures_dir.join(&entry.output);
+            if let Ok(output) = output.canonicalize() {
+                sources.insert(output, source);
+            }
+        }
+    }
+
+    let mut reverted = 0;
+    let mut unknown = 0;
+    let uris = gltf.image_uris();
+    let dir = gltf.dir().to_path_buf();
+    if let Some(images) = gltf.images_mut() {
+        for (index, uri) in uris {
+            if !has_extension(&uri, "ktx2") {
+                continue;
+            }
+            let output = dir.join(&uri).canonicalize().ok();
+            let Some(source) = output.and_then(|output| sources.get(&output)) else {
+                unknown += 1;
+                continue;
+            };
+            let extension = Path::new(source).extension().unwrap().to_string_lossy();
+            let image = images[index].as_object_mut().unwrap();
+            let uri = replace_uri_extension(image["uri"].as_str().unwrap(), &extension);
+            image.insert("uri".into(), uri.into());
+            if image.contains_key("mimeType") {
+                image.insert("mimeType".into(), format!("image/{extension}").into());
+            }
+            reverted += 1;
+        }
+    }
+    remove_ktx2_sources(&mut gltf);
+
+    gltf.save()?;
+    println!(
+        "{}: {reverted} images now use png, {unknown} KTX2 images not found in a manifest",
+        path.display()
+    );
+    Ok(())
+}
+
+/// Removes the sources added by `add_ktx2_sources`, along with their images. Older versions used
+/// `KHR_texture_basisu`, those are removed too.
+fn remove_ktx2_sources(gltf: &mut GltfDocument) {
+    let mut ktx2_images = Vec::new();
+    if let Some(textures) = gltf.json.get_mut("textures").and_then(|t| t.as_array_mut()) {
+        for texture in textures {
+            let Some(extensions) = texture
+                .get_mut("extensions")
+                .and_then(|e| e.as_object_mut())
+            else {
+                continue;
+            };
+            for name in [SPONZA_TEXTURE_KTX2, KHR_TEXTURE_BASISU] {
+                if let Some(extension) = extensions.remove(name) {
+                    ktx2_images.extend(extension["source"].as_u64().map(|s| s as usize));
+                }
+            }
+            if extensions.is_empty() {
+                texture.as_object_mut().unwrap().remove("extensions");
+            }
+        }
+    }
+    gltf.remove_extension_used(SPONZA_TEXTURE_KTX2);
+    gltf.remove_extension_used(KHR_TEXTURE_BASISU);
+
+    // The KTX2 images were appended, so they can be dropped from the end without renumbering anything
+    if let Some(images) = gltf.images_mut() {
+        while images
+            .len()
+            .checked_sub(1)
+            .is_some_and(|last| ktx2_images.contains(&last))
+        {
+            images.pop();
+        }
+    }
+}
+
+/// Packages can share a textures folder, so each folder is only listed once.
+fn textures_dirs(asset_root: &Path, scene: &SceneManifest) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    for package in &scene.packages {
+        let dir = package.textures_dir(asset_root);
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+fn has_extension(uri: &str, extension: &str) -> bool {
+    Path::new(uri)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(extension))
+}
+
+/// How the KTX2 files get encoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EncoderBackend {
+    /// In process block compression, works anywhere `cargo run` works.
+    Native,
+    /// Shells out to [kram](https://github.com/alecazam/kram), which needs to be in your path.
+    Kram,
+}
+
+impl FromStr for EncoderBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "native" => Ok(EncoderBackend::Native),
+            "kram" => Ok(EncoderBackend::Kram),
+            _ => Err(anyhow!("Unknown encoder {s}, expected native or kram")),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvertSettings {
+    pub backend: EncoderBackend,
+    pub color_format: BlockFormat,
+    pub normal_format: BlockFormat,
+    pub metallic_roughness_format: BlockFormat,
+    /// `None` disables zstd supercompression. Level 0 uses zstd's default level.
+    pub zstd_level: Option<i32>,
+    pub minimum_mip_resolution: u32,
+    pub filter_type: FilterType,
+    /// Re-encode every texture, even the ones the manifest says are up to date.
+    pub force: bool,
+    /// Reference the KTX2 files through `SPONZA_texture_ktx2` instead of replacing the png uris.
+    pub ktx2_extension: bool,
+    /// Number of textures encoded in parallel.
+    pub threads: usize,
+}
+
+impl Default for ConvertSettings {
+    fn default() -> Self {
+        Self {
+            backend: EncoderBackend::Native,
+            // should be able to use bc5 for nor and rough+metal, but they looked bad
+            color_format: BlockFormat::Bc7,
+            normal_format: BlockFormat::Bc7,
+            metallic_roughness_format: BlockFormat::Bc7,
+            zstd_level: Some(0),
+            minimum_mip_resolution: 2,
+            filter_type: FilterType::Triangle,
+            force: false,
+            ktx2_extension: false,
+            threads: available_parallelism().map_or(1, |n| n.get()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TextureClass {
+    Color,
+    Normal,
+    MetallicRoughness,
+}
+
+impl TextureClass {
+    /// Guesses the texture usage from the Sponza naming convention.
+    fn from_file_stem(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        if name.contains("normal") {
+            Some(TextureClass::Normal)
+        } else if name.contains("roughness") && name.contains("metalness") {
+            Some(TextureClass::MetallicRoughness)
+        } else if name.contains("basecolor") || name.contains("decal") || name.contains("emissive")
+        {
+            Some(TextureClass::Color)
+        } else {
+            None
+        }
+    }
+
+    fn is_srgb(self) -> bool {
+        self == TextureClass::Color
+    }
+}
+
+const KTX2_MIME_TYPE: &str = "image/ktx2";
+
+pub fn convert_images_to_ktx2(
+    asset_root: &Path,
+    scene: &SceneManifest,
+    settings: &ConvertSettings,
+) -> anyhow::Result<()> {
+    let failures = Arc::new(Mutex::new(Vec::new()));
+    for textures_dir in &textures_dirs(asset_root, scene) {
+        let previous = Arc::new(TextureManifest::load(textures_dir));
+        let manifest = Arc::new(Mutex::new(TextureManifest::default()));
+        let up_to_date = Arc::new(AtomicUsize::new(0));
+
+        let pool = ThreadPool::new(settings.threads);
+        let entries = match fs::read_dir(textures_dir) {
+            Ok(entries) => entries,
+            Err(e) => {
+                let error = format!("Failed to read {}: {e}", textures_dir.display());
+                failures.lock().unwrap().push(error);
+                continue;
+            }
+        };
+        for path in entries {
+            let settings = settings.clone();
+            let failures = failures.clone();
+            let previous = previous.clone();
+            let manifest = manifest.clone();
+            let up_to_date = up_to_date.clone();
             pool.execute(move || {
                 if let Ok(path) = path {
                     let path = path.path();
-                    if path.is_file() && path.extension().unwrap() == "png" {
-                        let path_string = path.to_string_lossy().to_string();
-                        let new_path_string =
-                            path.with_extension("ktx2").to_string_lossy().to_string();
-                        let name = path.file_stem().unwrap().to_string_lossy().to_lowercase();
-                        let nor = name.contains("normal");
-                        let metal_rough = name.contains("roughness") && name.contains("metalness");
-                        let color = name.contains("basecolor");
-                        let decal = name.contains("decal");
-
-                        if !nor && !metal_rough && !color && !decal {
+                    if path.is_file() && path.extension().is_some_and(|ext| ext == "png") {
+                        let name = path.file_stem().unwrap().to_string_lossy();
+                        let Some(class) = TextureClass::from_file_stem(&name) else {
                             return;
+                        };
+                        match convert_image(&path, class, &settings, &previous) {
+                            Ok((entry, converted)) => {
+                                if converted {
+                                    println!("{}", path.with_extension("ktx2").display());
+                                } else {
+                                    up_to_date.fetch_add(1, Ordering::Relaxed);
+                                }
+                                let source_name = path.file_name().unwrap().to_string_lossy();
+                                manifest
+                                    .lock()
+                                    .unwrap()
+                                    .entries
+                                    .insert(source_name.to_string(), entry);
+                            }
+                            Err(e) => {
+                                failures.lock().unwrap().push(format!("{e:#}"));
+                                // Keep track of the previous output, it's still on disk
+                                let source_name = path.file_name().unwrap().to_string_lossy();
+                                if let Some(entry) = previous.entries.get(source_name.as_ref()) {
+                                    manifest
+                                        .lock()
+                                        .unwrap()
+                                        .entries
+                                        .insert(source_name.to_string(), entry.clone());
+                                }
+                            }
                         }
-
-                        let mut cmd = Command::new("kram");
-                        cmd.arg("encode").arg("-f");
-                        // should be able to use bc5 for nor and rough+metal, but they looked bad
-                        cmd.arg("bc7");
-                        if nor {
-                            cmd.arg("-normal");
-                        }
-                        cmd.arg("-type")
-                            .arg("2d")
-                            .arg("-mipmin")
-                            .arg("2")
-                            .arg("-srgb")
-                            .arg("-zstd")
-                            .arg("0")
-                            .arg("-i")
-                            .arg(path_string)
-                            .arg("-o")
-                            .arg(new_path_string);
-                        dbg!(&cmd);
-                        cmd.output().expect("ls command failed to start");
                     }
                 }
             });
         }
         pool.join();
+
+        let up_to_date = up_to_date.load(Ordering::Relaxed);
+        if up_to_date > 0 {
+            println!(
+                "{}: {up_to_date} textures already up to date",
+                textures_dir.display()
+            );
+        }
+        let saved = manifest.lock().unwrap().save(textures_dir);
+        if let Err(e) = saved {
+            failures.lock().unwrap().push(format!("{e:#}"));
+        }
+    }
+
+    let failures = failures.lock().unwrap();
+    if !failures.is_empty() {
+        for failure in failures.iter() {
+            eprintln!("{failure}");
+        }
+        return Err(anyhow!("Failed to convert {} textures", failures.len()));
+    }
+    Ok(())
+}
+
+/// Converts a single png unless the manifest says its output is up to date.
+/// Returns the new manifest entry and whether the image had to be encoded.
+fn convert_image(
+    path: &Path,
+    class: TextureClass,
+    settings: &ConvertSettings,
+    previous: &TextureManifest,
+) -> anyhow::Result<(ManifestEntry, bool)> {
+    let textures_dir = path.parent().unwrap();
+    let source_name = path.file_name().unwrap().to_string_lossy();
+    let source_hash = hash_file(path)?;
+    let fingerprint = settings.fingerprint(class);
+
+    if !settings.force {
+        if let Some(entry) =
+            previous.up_to_date(textures_dir, &source_name, &source_hash, &fingerprint)
+        {
+            return Ok((entry.clone(), false));
+        }
+    }
+
+    let new_path = path.with_extension("ktx2");
+    match settings.backend {
+        EncoderBackend::Native => encode_native(path, &new_path, class, settings)?,
+        EncoderBackend::Kram => encode_kram(path, &new_path, class, settings)?,
+    }
+
+    let entry = ManifestEntry {
+        source_hash,
+        settings: fingerprint,
+        output: new_path.file_name().unwrap().to_string_lossy().to_string(),
+        output_hash: hash_file(&new_path)?,
+    };
+    Ok((entry, true))
+}
+
+fn encode_native(
+    path: &Path,
+    new_path: &Path,
+    class: TextureClass,
+    settings: &ConvertSettings,
+) -> anyhow::Result<()> {
+    let format = settings.format_for(class);
+    let mut image = image::open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?
+        .to_rgba8();
+    let (width, height) = image.dimensions();
+
+    let mut levels = vec![bcn::compress(&image, format)];
+    let minimum_mip_resolution = settings.minimum_mip_resolution.max(1);
+    while image.width() / 2 >= minimum_mip_resolution
+        && image.height() / 2 >= minimum_mip_resolution
+    {
+        image = image::imageops::resize(
+            &image,
+            image.width() / 2,
+            image.height() / 2,
+            settings.filter_type,
+        );
+        levels.push(bcn::compress(&image, format));
+    }
+
+    let texture = Ktx2Texture {
+        format,
+        srgb: settings.is_srgb(class),
+        width,
+        height,
+        levels,
+    };
+    let bytes = texture.to_bytes(settings.zstd_level)?;
+    fs::write(new_path, bytes).with_context(|| format!("Failed to write {}", new_path.display()))
+}
+
+fn encode_kram(
+    path: &Path,
+    new_path: &Path,
+    class: TextureClass,
+    settings: &ConvertSettings,
+) -> anyhow::Result<()> {
+    let mut cmd = Command::new("kram");
+    cmd.arg("encode").arg("-f");
+    cmd.arg(settings.format_for(class).kram_name());
+    if class == TextureClass::Normal {
+        cmd.arg("-normal");
+    }
+    cmd.arg("-type")
+        .arg("2d")
+        .arg("-mipmin")
+        .arg(settings.minimum_mip_resolution.to_string());
+    if settings.is_srgb(class) {
+        cmd.arg("-srgb");
+    }
+    if let Some(level) = settings.zstd_level {
+        cmd.arg("-zstd").arg(level.to_string());
+    }
+    cmd.arg("-i").arg(path).arg("-o").arg(new_path);
+
+    let output = cmd
+        .output()
+        .context("Failed to start kram, is it in your path?")?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "kram failed on {}: {}",
+            path.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+impl ConvertSettings {
+    /// Describes everything that affects the encoded output of a texture of this class.
+    fn fingerprint(&self, class: TextureClass) -> String {
+        format!(
+            "{:?} {:?} srgb={} zstd={:?} mipmin={} filter={:?}",
+            self.backend,
+            self.format_for(class),
+            self.is_srgb(class),
+            self.zstd_level,
+            self.minimum_mip_resolution,
+            self.filter_type,
+        )
+    }
+
+    /// BC4 and BC5 have no sRGB variants, so color textures encoded with them are stored linear.
+    fn is_srgb(&self, class: TextureClass) -> bool {
+        class.is_srgb() && self.format_for(class) == BlockFormat::Bc7
+    }
+
+    fn format_for(&self, class: TextureClass) -> BlockFormat {
+        match class {
+            TextureClass::Color => self.color_format,
+            TextureClass::Normal => self.normal_format,
+            TextureClass::MetallicRoughness => self.metallic_roughness_format,
+        }
+    }
+}
+
+impl BlockFormat {
+    fn kram_name(self) -> &'static str {
+        match self {
+            BlockFormat::Bc4 => "bc4",
+            BlockFormat::Bc5 => "bc5",
+            BlockFormat::Bc7 => "bc7",
+        }
     }
 }
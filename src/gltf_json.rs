@@ -0,0 +1,163 @@
+//! Reading and editing `.gltf` files as JSON, without going through the asset server.
+
+use anyhow::{anyhow, Context};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Vendor extension pointing a texture at its block compressed KTX2 image. `KHR_texture_basisu`
+/// would be the standard one, but it only allows Basis Universal payloads.
+pub const SPONZA_TEXTURE_KTX2: &str = "SPONZA_texture_ktx2";
+/// Written by older versions of `convert`, only read so the files can be reverted.
+pub const KHR_TEXTURE_BASISU: &str = "KHR_texture_basisu";
+
+pub struct GltfDocument {
+    pub path: PathBuf,
+    pub json: Value,
+}
+
+impl GltfDocument {
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let contents = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let json = serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Self { path, json })
+    }
+
+    pub fn save(&self) -> anyhow::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.json)?;
+        fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+
+    /// Folder that relative uris are resolved against.
+    pub fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or_else(|| Path::new("."))
+    }
+
+    /// Where `convert` keeps the original of the glTF file at `path`.
+    pub fn backup_path(path: &Path) -> PathBuf {
+        let mut path = path.to_path_buf().into_os_string();
+        path.push(".bak");
+        path.into()
+    }
+
+    pub fn images_mut(&mut self) -> Option<&mut Vec<Value>> {
+        self.json.get_mut("images")?.as_array_mut()
+    }
+
+    /// Returns the index and decoded uri of every image stored in an external file.
+    pub fn image_uris(&self) -> Vec<(usize, String)> {
+        self.external_uris("images")
+    }
+
+    /// Returns the index and decoded uri of every buffer stored in an external file.
+    pub fn buffer_uris(&self) -> Vec<(usize, String)> {
+        self.external_uris("buffers")
+    }
+
+    pub fn materials(&self) -> &[Value] {
+        self.json
+            .get("materials")
+            .and_then(Value::as_array)
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// Decoded uri of the image used by a texture, the KTX2 one when it goes through an extension.
+    pub fn texture_uri(&self, texture: usize) -> Option<String> {
+        let texture = self.json.get("textures")?.get(texture)?;
+        let source = texture
+            .pointer("/extensions/SPONZA_texture_ktx2/source")
+            .or_else(|| texture.pointer("/extensions/KHR_texture_basisu/source"))
+            .or_else(|| texture.get("source"))?
+            .as_u64()?;
+        let image = self.json.get("images")?.get(source as usize)?;
+        Some(match image.get("uri").and_then(Value::as_str) {
+            Some(uri) if !uri.starts_with("data:") => decode_uri(uri),
+            _ => "<embedded>".to_string(),
+        })
+    }
+
+    fn external_uris(&self, key: &str) -> Vec<(usize, String)> {
+        let Some(items) = self.json.get(key).and_then(Value::as_array) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                let uri = item.get("uri")?.as_str()?;
+                (!uri.starts_with("data:")).then(|| (i, decode_uri(uri)))
+            })
+            .collect()
+    }
+
+    pub fn uses_extension(&self, name: &str) -> bool {
+        self.json
+            .get("extensionsUsed")
+            .and_then(Value::as_array)
+            .is_some_and(|used| used.iter().any(|e| e == name))
+    }
+
+    pub fn add_extension_used(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.uses_extension(name) {
+            return Ok(());
+        }
+        let root = self
+            .json
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("{} is not a glTF object", self.path.display()))?;
+        root.entry("extensionsUsed")
+            .or_insert_with(|| json!([]))
+            .as_array_mut()
+            .ok_or_else(|| anyhow!("extensionsUsed is not an array"))?
+            .push(json!(name));
+        Ok(())
+    }
+
+    pub fn remove_extension_used(&mut self, name: &str) {
+        let Some(root) = self.json.as_object_mut() else {
+            return;
+        };
+        for key in ["extensionsUsed", "extensionsRequired"] {
+            if let Some(used) = root.get_mut(key).and_then(Value::as_array_mut) {
+                used.retain(|e| e != name);
+                if used.is_empty() {
+                    root.remove(key);
+                }
+            }
+        }
+    }
+}
+
+/// Decodes the `%XX` escapes that glTF exporters use for spaces and other special characters.
+pub fn decode_uri(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(byte) = hex.and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Swaps the extension of a uri while keeping any escapes in the file name intact.
+pub fn replace_uri_extension(uri: &str, extension: &str) -> String {
+    match uri.rfind('.') {
+        Some(dot) if !uri[dot..].contains('/') => format!("{}.{extension}", &uri[..dot]),
+        _ => format!("{uri}.{extension}"),
+    }
+}
@@ -0,0 +1,358 @@
+//! Walks entity hierarchies without recursion, so deep glTF scenes can't overflow the stack.
+//!
+//! ```ignore
+//! let mut iter = descendants(root, &children_query);
+//! while let Some(visit) = iter.next() {
+//!     if hidden.contains(visit.entity) {
+//!         iter.skip_children();
+//!     }
+//! }
+//! ```
+
+use bevy::{
+    ecs::query::{ROQueryItem, ReadOnlyWorldQuery, WorldQuery},
+    prelude::*,
+};
+
+/// An entity reached by the traversal, the direct children of the root are at depth 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Visit {
+    pub entity: Entity,
+    pub depth: usize,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TraversalOrder {
+    /// Parents before their children.
+    #[default]
+    PreOrder,
+    /// Children before their parents, like `despawn_recursive`.
+    #[cfg_attr(not(test), allow(dead_code))]
+    PostOrder,
+}
+
+/// Returns the descendants of `root`, not including `root` itself, in pre-order by default.
+pub fn descendants<'a>(root: Entity, children: &'a Query<&Children>) -> Descendants<'a> {
+    Descendants {
+        children: Box::new(|entity| children.get(entity).ok()),
+        order: TraversalOrder::PreOrder,
+        max_depth: usize::MAX,
+        prune: None,
+        root: Some(Visit {
+            entity: root,
+            depth: 0,
+        }),
+        stack: Vec::new(),
+        expand: None,
+    }
+}
+
+pub struct Descendants<'a> {
+    children: Box<dyn Fn(Entity) -> Option<&'a Children> + 'a>,
+    order: TraversalOrder,
+    max_depth: usize,
+    prune: Option<Box<dyn FnMut(Visit) -> bool + 'a>>,
+    /// Its children are queued on the first call, once the options are set.
+    root: Option<Visit>,
+    /// Entities left to visit, the next one is at the end.
+    stack: Vec<Frame>,
+    /// Last entity returned in pre-order, its children are queued on the next call.
+    expand: Option<Visit>,
+}
+
+struct Frame {
+    visit: Visit,
+    /// Post-order only, the children were queued and the entity is visited once they're done.
+    expanded: bool,
+}
+
+impl<'a> Descendants<'a> {
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn order(mut self, order: TraversalOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Doesn't go deeper than `max_depth`, 1 only returns the direct children and 0 nothing.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Doesn't go into the children of the entities `prune` returns true for.
+    /// The pruned entities are still returned.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn prune(mut self, prune: impl FnMut(Visit) -> bool + 'a) -> Self {
+        self.prune = Some(Box::new(prune));
+        self
+    }
+
+    /// Doesn't go into the children of the entity returned last. Only works in pre-order,
+    /// in post-order the children have already been returned.
+    pub fn skip_children(&mut self) {
+        self.expand = None;
+    }
+
+    /// Only returns the entities matching `query`, along with their query item.
+    pub fn matching<'q, 'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery>(
+        self,
+        query: &'q Query<'w, 's, Q, F>,
+    ) -> Matching<'a, 'q, 'w, 's, Q, F> {
+        Matching {
+            descendants: self,
+            query,
+        }
+    }
+
+    fn is_pruned(&mut self, visit: Visit) -> bool {
+        self.prune.as_mut().is_some_and(|prune| prune(visit))
+    }
+
+    fn push_children(&mut self, parent: Visit) {
+        if parent.depth >= self.max_depth {
+            return;
+        }
+        let Some(children) = (self.children)(parent.entity) else {
+            return;
+        };
+        // Reversed so the first child is popped first
+        self.stack
+            .extend(children.iter().rev().map(|&entity| Frame {
+                visit: Visit {
+                    entity,
+                    depth: parent.depth + 1,
+                },
+                expanded: false,
+            }));
+    }
+}
+
+impl<'a> Iterator for Descendants<'a> {
+    type Item = Visit;
+
+    fn next(&mut self) -> Option<Visit> {
+        if let Some(root) = self.root.take() {
+            self.push_children(root);
+        }
+        match self.order {
+            TraversalOrder::PreOrder => {
+                if let Some(parent) = self.expand.take() {
+                    self.push_children(parent);
+                }
+                let visit = self.stack.pop()?.visit;
+                if !self.is_pruned(visit) {
+                    self.expand = Some(visit);
+                }
+                Some(visit)
+            }
+            TraversalOrder::PostOrder => loop {
+                let frame = self.stack.last_mut()?;
+                if frame.expanded {
+                    return self.stack.pop().map(|frame| frame.visit);
+                }
+                frame.expanded = true;
+                let visit = frame.visit;
+                if !self.is_pruned(visit) {
+                    self.push_children(visit);
+                }
+            },
+        }
+    }
+}
+
+/// Iterator returned by [`Descendants::matching`].
+pub struct Matching<'a, 'q, 'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery> {
+    descendants: Descendants<'a>,
+    query: &'q Query<'w, 's, Q, F>,
+}
+
+impl<'a, 'q, 'w, 's, Q: WorldQuery, F: ReadOnlyWorldQuery> Iterator
+    for Matching<'a, 'q, 'w, 's, Q, F>
+{
+    type Item = (Visit, ROQueryItem<'q, Q>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let query = self.query;
+        self.descendants
+            .by_ref()
+            .find_map(|visit| Some((visit, query.get(visit.entity).ok()?)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    #[derive(Component)]
+    struct Marker;
+
+    /// root
+    /// ├── a
+    /// │   ├── a1
+    /// │   │   └── a1x
+    /// │   └── a2
+    /// └── b
+    ///     └── b1
+    fn world() -> (World, Entity) {
+        let mut world = World::new();
+        let mut spawn = |name: &str, children: &[Entity]| {
+            let mut entity = world.spawn(Name::new(name.to_string()));
+            entity.push_children(children);
+            entity.id()
+        };
+        let a1x = spawn("a1x", &[]);
+        let a1 = spawn("a1", &[a1x]);
+        let a2 = spawn("a2", &[]);
+        let a = spawn("a", &[a1, a2]);
+        let b1 = spawn("b1", &[]);
+        let b = spawn("b", &[b1]);
+        let root = spawn("root", &[a, b]);
+        for entity in [a1, b1] {
+            world.entity_mut(entity).insert(Marker);
+        }
+        (world, root)
+    }
+
+    fn names(world: &World, visits: impl Iterator<Item = Visit>) -> Vec<(String, usize)> {
+        visits
+            .map(|visit| {
+                (
+                    world.get::<Name>(visit.entity).unwrap().to_string(),
+                    visit.depth,
+                )
+            })
+            .collect()
+    }
+
+    fn expected(visits: &[(&str, usize)]) -> Vec<(String, usize)> {
+        visits
+            .iter()
+            .map(|&(name, depth)| (name.to_string(), depth))
+            .collect()
+    }
+
+    #[test]
+    fn pre_order() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        assert_eq!(
+            names(&world, descendants(root, &children)),
+            expected(&[
+                ("a", 1),
+                ("a1", 2),
+                ("a1x", 3),
+                ("a2", 2),
+                ("b", 1),
+                ("b1", 2)
+            ])
+        );
+    }
+
+    #[test]
+    fn post_order() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        let visits = descendants(root, &children).order(TraversalOrder::PostOrder);
+        assert_eq!(
+            names(&world, visits),
+            expected(&[
+                ("a1x", 3),
+                ("a1", 2),
+                ("a2", 2),
+                ("a", 1),
+                ("b1", 2),
+                ("b", 1)
+            ])
+        );
+    }
+
+    #[test]
+    fn leaf_has_no_descendants() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        let b1 = descendants(root, &children).last().unwrap().entity;
+        assert_eq!(descendants(b1, &children).count(), 0);
+        let visits = descendants(b1, &children).order(TraversalOrder::PostOrder);
+        assert_eq!(visits.count(), 0);
+    }
+
+    #[test]
+    fn max_depth() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        assert_eq!(
+            names(&world, descendants(root, &children).max_depth(1)),
+            expected(&[("a", 1), ("b", 1)])
+        );
+        let visits = descendants(root, &children)
+            .order(TraversalOrder::PostOrder)
+            .max_depth(2);
+        assert_eq!(
+            names(&world, visits),
+            expected(&[("a1", 2), ("a2", 2), ("a", 1), ("b1", 2), ("b", 1)])
+        );
+        assert_eq!(descendants(root, &children).max_depth(0).count(), 0);
+    }
+
+    #[test]
+    fn prune() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        let a = descendants(root, &children).next().unwrap().entity;
+        let is_a = move |visit: Visit| visit.entity == a;
+
+        // The pruned entity is still returned, without its children
+        let visits = descendants(root, &children).prune(is_a);
+        assert_eq!(
+            names(&world, visits),
+            expected(&[("a", 1), ("b", 1), ("b1", 2)])
+        );
+        let visits = descendants(root, &children)
+            .order(TraversalOrder::PostOrder)
+            .prune(is_a);
+        assert_eq!(
+            names(&world, visits),
+            expected(&[("a", 1), ("b1", 2), ("b", 1)])
+        );
+    }
+
+    #[test]
+    fn skip_children() {
+        let (mut world, root) = world();
+        let mut state = SystemState::<Query<&Children>>::new(&mut world);
+        let children = state.get(&world);
+        let mut visits = Vec::new();
+        let mut iter = descendants(root, &children);
+        while let Some(visit) = iter.next() {
+            if world.get::<Name>(visit.entity).unwrap().as_str() == "a1" {
+                iter.skip_children();
+            }
+            visits.push(visit);
+        }
+        assert_eq!(
+            names(&world, visits.into_iter()),
+            expected(&[("a", 1), ("a1", 2), ("a2", 2), ("b", 1), ("b1", 2)])
+        );
+    }
+
+    #[test]
+    fn matching() {
+        let (mut world, root) = world();
+        let mut state =
+            SystemState::<(Query<&Children>, Query<&Name, With<Marker>>)>::new(&mut world);
+        let (children, marked) = state.get(&world);
+        let matches: Vec<_> = descendants(root, &children)
+            .matching(&marked)
+            .map(|(visit, name)| (name.to_string(), visit.depth))
+            .collect();
+        assert_eq!(matches, expected(&[("a1", 2), ("b1", 2)]));
+    }
+}
@@ -0,0 +1,268 @@
+//! Minimal KTX2 container writer for block compressed 2D textures.
+//! See https://registry.khronos.org/KTX/specs/2.0/ktxspec.v2.html
+
+use anyhow::Context;
+
+use crate::bcn::BlockFormat;
+
+const IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+const SUPERCOMPRESSION_NONE: u32 = 0;
+const SUPERCOMPRESSION_ZSTD: u32 = 2;
+
+// Khronos data format descriptor constants
+const KHR_DF_MODEL_BC4: u8 = 131;
+const KHR_DF_MODEL_BC5: u8 = 132;
+const KHR_DF_MODEL_BC7: u8 = 134;
+const KHR_DF_PRIMARIES_BT709: u8 = 1;
+const KHR_DF_TRANSFER_LINEAR: u8 = 1;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+const KHR_DF_VERSION: u32 = 2;
+
+pub struct Ktx2Texture {
+    pub format: BlockFormat,
+    pub srgb: bool,
+    pub width: u32,
+    pub height: u32,
+    /// Block compressed data for each mip level, starting with the full resolution level.
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl Ktx2Texture {
+    fn vk_format(&self) -> u32 {
+        match (self.format, self.srgb) {
+            (BlockFormat::Bc4, _) => 139,
+            (BlockFormat::Bc5, _) => 141,
+            (BlockFormat::Bc7, false) => 145,
+            (BlockFormat::Bc7, true) => 146,
+        }
+    }
+
+    fn data_format_descriptor(&self) -> Vec<u8> {
+        // (bit offset, bit length, channel id) for each sample
+        let (color_model, samples): (u8, &[(u16, u8, u8)]) = match self.format {
+            BlockFormat::Bc4 => (KHR_DF_MODEL_BC4, &[(0, 64, 0)]),
+            BlockFormat::Bc5 => (KHR_DF_MODEL_BC5, &[(0, 64, 0), (64, 64, 1)]),
+            BlockFormat::Bc7 => (KHR_DF_MODEL_BC7, &[(0, 128, 0)]),
+        };
+        let block_size = 24 + 16 * samples.len() as u32;
+
+        let mut dfd = Vec::new();
+        dfd.extend_from_slice(&(4 + block_size).to_le_bytes());
+        // vendor id and descriptor type are both 0 for the basic descriptor block
+        dfd.extend_from_slice(&0u32.to_le_bytes());
+        dfd.extend_from_slice(&(KHR_DF_VERSION | block_size << 16).to_le_bytes());
+        dfd.push(color_model);
+        dfd.push(KHR_DF_PRIMARIES_BT709);
+        dfd.push(if self.srgb {
+            KHR_DF_TRANSFER_SRGB
+        } else {
+            KHR_DF_TRANSFER_LINEAR
+        });
+        dfd.push(0); // flags, straight alpha
+        dfd.extend_from_slice(&[3, 3, 0, 0]); // 4x4x1x1 blocks, stored minus one
+        let mut bytes_planes = [0u8; 8];
+        bytes_planes[0] = self.format.block_bytes() as u8;
+        dfd.extend_from_slice(&bytes_planes);
+        for &(bit_offset, bit_length, channel) in samples {
+            dfd.extend_from_slice(&bit_offset.to_le_bytes());
+            dfd.push(bit_length - 1);
+            dfd.push(channel);
+            dfd.extend_from_slice(&[0, 0, 0, 0]); // sample position
+            dfd.extend_from_slice(&0u32.to_le_bytes()); // sample lower
+            dfd.extend_from_slice(&u32::MAX.to_le_bytes()); // sample upper
+        }
+        dfd
+    }
+
+    /// Serializes the texture. `zstd_level` enables Zstandard supercompression of each level.
+    pub fn to_bytes(&self, zstd_level: Option<i32>) -> anyhow::Result<Vec<u8>> {
+        let levels = match zstd_level {
+            Some(level) => self
+                .levels
+                .iter()
+                .map(|data| zstd::bulk::compress(data, level))
+                .collect::<Result<Vec<_>, _>>()
+                .context("Failed to zstd compress mip level")?,
+            None => self.levels.clone(),
+        };
+        let level_count = levels.len() as u32;
+
+        let header_size = IDENTIFIER.len() + 9 * 4 + 4 * 4 + 2 * 8;
+        let level_index_size = levels.len() * 3 * 8;
+        let dfd = self.data_format_descriptor();
+        let dfd_offset = header_size + level_index_size;
+
+        let mut kvd = Vec::new();
+        let writer = b"KTXwriter\0sponza\0";
+        kvd.extend_from_slice(&(writer.len() as u32).to_le_bytes());
+        kvd.extend_from_slice(writer);
+        pad_to(&mut kvd, 4);
+        let kvd_offset = dfd_offset + dfd.len();
+
+        let mut out = Vec::new();
+        out.extend_from_slice(&IDENTIFIER);
+        for value in [
+            self.vk_format(),
+            1, // type size is 1 for block compressed formats
+            self.width,
+            self.height,
+            0, // depth
+            0, // layer count
+            1, // face count
+            level_count,
+            if zstd_level.is_some() {
+                SUPERCOMPRESSION_ZSTD
+            } else {
+                SUPERCOMPRESSION_NONE
+            },
+            dfd_offset as u32,
+            dfd.len() as u32,
+            kvd_offset as u32,
+            kvd.len() as u32,
+        ] {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        // No supercompression global data
+        out.extend_from_slice(&0u64.to_le_bytes());
+        out.extend_from_slice(&0u64.to_le_bytes());
+
+        // The level index is filled once we know where each level lands
+        let level_index_offset = out.len();
+        out.resize(out.len() + level_index_size, 0);
+        out.extend_from_slice(&dfd);
+        out.extend_from_slice(&kvd);
+
+        // Level data is stored from the smallest mip to the largest
+        let alignment = if zstd_level.is_some() {
+            1
+        } else {
+            self.format.block_bytes()
+        };
+        let mut index = vec![(0u64, 0u64, 0u64); levels.len()];
+        for (level, data) in levels.iter().enumerate().rev() {
+            pad_to(&mut out, alignment);
+            index[level] = (
+                out.len() as u64,
+                data.len() as u64,
+                self.levels[level].len() as u64,
+            );
+            out.extend_from_slice(data);
+        }
+
+        for (i, (offset, length, uncompressed_length)) in index.into_iter().enumerate() {
+            let start = level_index_offset + i * 24;
+            out[start..start + 8].copy_from_slice(&offset.to_le_bytes());
+            out[start + 8..start + 16].copy_from_slice(&length.to_le_bytes());
+            out[start + 16..start + 24].copy_from_slice(&uncompressed_length.to_le_bytes());
+        }
+
+        Ok(out)
+    }
+}
+
+fn pad_to(data: &mut Vec<u8>, alignment: usize) {
+    while data.len() % alignment != 0 {
+        data.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn texture(format: BlockFormat, srgb: bool) -> Ktx2Texture {
+        let block_bytes = format.block_bytes();
+        Ktx2Texture {
+            format,
+            srgb,
+            width: 8,
+            height: 8,
+            levels: vec![vec![1; 4 * block_bytes], vec![2; block_bytes]],
+        }
+    }
+
+    #[test]
+    fn header() {
+        let bytes = texture(BlockFormat::Bc7, true).to_bytes(None).unwrap();
+        assert_eq!(bytes[..12], IDENTIFIER);
+        let header: Vec<u32> = (0..9).map(|i| u32_at(&bytes, 12 + 4 * i)).collect();
+        // vkFormat, typeSize, width, height, depth, layers, faces, levels, supercompression
+        assert_eq!(header, [146, 1, 8, 8, 0, 0, 1, 2, SUPERCOMPRESSION_NONE]);
+        // The DFD follows the level index, then the key/value data
+        let dfd_offset = u32_at(&bytes, 48) as usize;
+        let dfd_length = u32_at(&bytes, 52) as usize;
+        assert_eq!(dfd_offset, 80 + 2 * 24);
+        assert_eq!(u32_at(&bytes, 56) as usize, dfd_offset + dfd_length);
+        assert!(u32_at(&bytes, 60) > 0);
+        assert_eq!(u64_at(&bytes, 64), 0);
+        assert_eq!(u64_at(&bytes, 72), 0);
+    }
+
+    #[test]
+    fn level_index() {
+        let texture = texture(BlockFormat::Bc7, false);
+        let bytes = texture.to_bytes(None).unwrap();
+        let mut previous_offset = usize::MAX;
+        for (level, data) in texture.levels.iter().enumerate() {
+            let offset = u64_at(&bytes, 80 + level * 24) as usize;
+            assert_eq!(u64_at(&bytes, 88 + level * 24), data.len() as u64);
+            assert_eq!(u64_at(&bytes, 96 + level * 24), data.len() as u64);
+            assert_eq!(offset % 16, 0);
+            assert_eq!(&bytes[offset..offset + data.len()], data);
+            // Smaller levels come first
+            assert!(offset < previous_offset);
+            previous_offset = offset;
+        }
+        // So the full resolution level ends the file
+        let offset = u64_at(&bytes, 80) as usize;
+        assert_eq!(offset + texture.levels[0].len(), bytes.len());
+    }
+
+    #[test]
+    fn zstd_levels() {
+        let texture = texture(BlockFormat::Bc5, false);
+        let bytes = texture.to_bytes(Some(0)).unwrap();
+        assert_eq!(u32_at(&bytes, 44), SUPERCOMPRESSION_ZSTD);
+        for (level, data) in texture.levels.iter().enumerate() {
+            let offset = u64_at(&bytes, 80 + level * 24) as usize;
+            let length = u64_at(&bytes, 88 + level * 24) as usize;
+            assert_eq!(u64_at(&bytes, 96 + level * 24), data.len() as u64);
+            let decompressed =
+                zstd::bulk::decompress(&bytes[offset..offset + length], data.len()).unwrap();
+            assert_eq!(&decompressed, data);
+        }
+    }
+
+    #[test]
+    fn data_format_descriptor() {
+        for (format, srgb, model, samples) in [
+            (BlockFormat::Bc4, false, KHR_DF_MODEL_BC4, 1),
+            (BlockFormat::Bc5, false, KHR_DF_MODEL_BC5, 2),
+            (BlockFormat::Bc7, true, KHR_DF_MODEL_BC7, 1),
+        ] {
+            let dfd = texture(format, srgb).data_format_descriptor();
+            assert_eq!(u32_at(&dfd, 0) as usize, dfd.len());
+            assert_eq!(dfd.len(), 4 + 24 + 16 * samples);
+            assert_eq!(u32_at(&dfd, 8) >> 16, 24 + 16 * samples as u32);
+            assert_eq!(dfd[12], model);
+            let transfer = if srgb {
+                KHR_DF_TRANSFER_SRGB
+            } else {
+                KHR_DF_TRANSFER_LINEAR
+            };
+            assert_eq!(dfd[14], transfer);
+            assert_eq!(dfd[20] as usize, format.block_bytes());
+        }
+    }
+}
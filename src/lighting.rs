@@ -0,0 +1,913 @@
+//! The fake GI light rig, described in `.lighting.ron` files under the asset root so it
+//! can be tuned without recompiling. Each file is a named preset that can be switched to at runtime.
+
+use bevy::{
+    asset::{AssetLoader, LoadContext, LoadState, LoadedAsset},
+    ecs::system::EntityCommands,
+    prelude::*,
+    reflect::TypeUuid,
+    utils::{BoxedFuture, HashMap, HashSet},
+};
+use serde::Deserialize;
+
+pub struct LightingPlugin;
+
+impl Plugin for LightingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<LightingPreset>()
+            .init_asset_loader::<LightingPresetLoader>()
+            .add_startup_system(load_lighting_presets)
+            .add_system(sync_lighting_rig.label(LightingSystems))
+            .add_system(
+                cycle_lighting_preset
+                    .label(LightingSystems)
+                    .after(sync_lighting_rig),
+            )
+            .add_system(
+                fade_lighting
+                    .label(LightingSystems)
+                    .after(cycle_lighting_preset),
+            );
+    }
+}
+
+/// Systems updating the rig lights, anything modifying them afterwards should run after this.
+#[derive(SystemLabel)]
+pub struct LightingSystems;
+
+const PRESET_EXTENSION: &str = ".lighting.ron";
+
+#[derive(Resource, Clone)]
+pub struct LightingSettings {
+    /// Folder with the presets, relative to the asset root.
+    pub folder: String,
+    /// File name of the preset to start with, without the extension.
+    pub preset: String,
+    /// How long switching presets takes, in seconds.
+    pub transition: f32,
+    pub key_next: KeyCode,
+}
+
+impl Default for LightingSettings {
+    fn default() -> Self {
+        Self {
+            folder: "lighting".into(),
+            preset: "grif".into(),
+            transition: 1.0,
+            key_next: KeyCode::L,
+        }
+    }
+}
+
+impl LightingSettings {
+    pub fn print_controls(&self) {
+        println!(
+            "
+===============================
+=========== Lighting ==========
+===============================
+    {:?} - Next Preset
+",
+            self.key_next,
+        );
+    }
+}
+
+/// Lights spawned from a preset, as opposed to the ones that come with the gltf files.
+#[derive(Component)]
+pub struct GrifLight;
+
+/// Values from the preset that the time of day starts from.
+/// For directional lights the intensity is the illuminance.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct SunDriven {
+    pub color: Color,
+    pub intensity: f32,
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub bounce: Option<Bounce>,
+}
+
+#[derive(Deserialize, TypeUuid, Debug)]
+#[uuid = "5f4c3b8e-8a0e-4c61-9d3c-2f1f6b3e7a41"]
+pub struct LightingPreset {
+    pub lights: Vec<LightDescription>,
+    /// Keeps the current ambient light when not set.
+    #[serde(default)]
+    pub ambient: Option<Ambient>,
+    /// sRGB, keeps the current clear color when not set.
+    #[serde(default)]
+    pub clear_color: Option<[f32; 3]>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Ambient {
+    /// sRGB, like `Color::rgb`.
+    pub color: [f32; 3],
+    pub brightness: f32,
+}
+
+impl LightingPreset {
+    fn ambient_light(&self) -> Option<AmbientLight> {
+        self.ambient.as_ref().map(|ambient| {
+            let [r, g, b] = ambient.color;
+            AmbientLight {
+                color: Color::rgb(r, g, b),
+                brightness: ambient.brightness,
+            }
+        })
+    }
+
+    fn clear_color(&self) -> Option<Color> {
+        self.clear_color.map(|[r, g, b]| Color::rgb(r, g, b))
+    }
+
+    /// Lights are matched by name when the preset is reloaded or switched, so names must be unique.
+    fn check_names(&self) -> anyhow::Result<()> {
+        let mut names = HashSet::new();
+        for light in &self.lights {
+            if !names.insert(light.name.as_str()) {
+                return Err(anyhow::anyhow!(
+                    "Light \"{}\" appears more than once in the preset",
+                    light.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LightDescription {
+    pub name: String,
+    pub kind: LightKind,
+    #[serde(default)]
+    pub transform: LightTransform,
+    /// sRGB, like `Color::rgb`.
+    pub color: [f32; 3],
+    #[serde(default)]
+    pub shadows: Option<Shadows>,
+    /// Fake bounce lights that get dimmed and tinted with the sun by the time of day.
+    /// Directional lights always follow the sun.
+    #[serde(default)]
+    pub follows_sun: bool,
+    /// Also moves and re-aims a `follows_sun` light with the sun.
+    #[serde(default)]
+    pub bounce: Option<Bounce>,
+}
+
+/// Where the sunlight a bounce light fakes comes from. The preset places the light for the
+/// preset sun, the time of day moves it with the patch of sunlight and turns it with the sun.
+#[derive(Deserialize, Clone, Copy, Debug)]
+pub struct Bounce {
+    /// Height of the opening the sun comes through, above the patch of sunlight.
+    pub opening_height: f32,
+    /// Normal of the surface the sunlight is reflected off, the light is aimed along the
+    /// sunlight when not set.
+    #[serde(default)]
+    pub reflected_off: Option<[f32; 3]>,
+    /// How far the light can move from where the preset puts it, for when the sun is low.
+    #[serde(default = "default_max_offset")]
+    pub max_offset: f32,
+}
+
+fn default_max_offset() -> f32 {
+    10.0
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum LightKind {
+    Directional {
+        illuminance: f32,
+        /// Half the size of the orthographic shadow projection.
+        shadow_half_size: f32,
+    },
+    Point {
+        intensity: f32,
+        range: f32,
+        #[serde(default)]
+        radius: f32,
+    },
+    Spot {
+        intensity: f32,
+        range: f32,
+        #[serde(default)]
+        radius: f32,
+        /// In degrees.
+        inner_angle: f32,
+        /// In degrees.
+        outer_angle: f32,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LightType {
+    Directional,
+    Point,
+    Spot,
+}
+
+impl LightKind {
+    pub fn light_type(&self) -> LightType {
+        match self {
+            LightKind::Directional { .. } => LightType::Directional,
+            LightKind::Point { .. } => LightType::Point,
+            LightKind::Spot { .. } => LightType::Spot,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct LightTransform {
+    pub translation: [f32; 3],
+    /// XYZ euler angles, in degrees. Ignored when `looking_at` is set.
+    pub rotation: [f32; 3],
+    pub looking_at: Option<LookAt>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct LookAt {
+    pub target: [f32; 3],
+    pub up: [f32; 3],
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct Shadows {
+    pub depth_bias: f32,
+    pub normal_bias: f32,
+}
+
+impl From<&LightTransform> for Transform {
+    fn from(transform: &LightTransform) -> Self {
+        let translation = Vec3::from(transform.translation);
+        match &transform.looking_at {
+            Some(look_at) => Transform::from_translation(translation)
+                .looking_at(Vec3::from(look_at.target), Vec3::from(look_at.up)),
+            None => {
+                let [x, y, z] = transform.rotation.map(f32::to_radians);
+                Transform::from_translation(translation).with_rotation(Quat::from_euler(
+                    EulerRot::XYZ,
+                    x,
+                    y,
+                    z,
+                ))
+            }
+        }
+    }
+}
+
+/// The light component of a [`LightDescription`], without the rest of its bundle.
+#[derive(Clone)]
+pub enum Light {
+    Directional(DirectionalLight),
+    Point(PointLight),
+    Spot(SpotLight),
+}
+
+impl Light {
+    pub fn light_type(&self) -> LightType {
+        match self {
+            Light::Directional(_) => LightType::Directional,
+            Light::Point(_) => LightType::Point,
+            Light::Spot(_) => LightType::Spot,
+        }
+    }
+
+    fn insert(self, entity: &mut EntityCommands) {
+        match self {
+            Light::Directional(light) => entity.insert(light),
+            Light::Point(light) => entity.insert(light),
+            Light::Spot(light) => entity.insert(light),
+        };
+    }
+
+    fn intensity_mut(&mut self) -> &mut f32 {
+        match self {
+            Light::Directional(light) => &mut light.illuminance,
+            Light::Point(light) => &mut light.intensity,
+            Light::Spot(light) => &mut light.intensity,
+        }
+    }
+
+    /// Blends two lights of the same type, returns `to` if they have different types.
+    fn lerp(&self, to: &Light, t: f32) -> Light {
+        match (self, to) {
+            (Light::Directional(from), Light::Directional(to)) => {
+                Light::Directional(DirectionalLight {
+                    color: lerp_color(from.color, to.color, t),
+                    illuminance: lerp(from.illuminance, to.illuminance, t),
+                    ..to.clone()
+                })
+            }
+            (Light::Point(from), Light::Point(to)) => Light::Point(PointLight {
+                color: lerp_color(from.color, to.color, t),
+                intensity: lerp(from.intensity, to.intensity, t),
+                range: lerp(from.range, to.range, t),
+                radius: lerp(from.radius, to.radius, t),
+                ..*to
+            }),
+            (Light::Spot(from), Light::Spot(to)) => Light::Spot(SpotLight {
+                color: lerp_color(from.color, to.color, t),
+                intensity: lerp(from.intensity, to.intensity, t),
+                range: lerp(from.range, to.range, t),
+                radius: lerp(from.radius, to.radius, t),
+                inner_angle: lerp(from.inner_angle, to.inner_angle, t),
+                outer_angle: lerp(from.outer_angle, to.outer_angle, t),
+                ..*to
+            }),
+            _ => to.clone(),
+        }
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+fn lerp_color(from: Color, to: Color, t: f32) -> Color {
+    let from = Vec4::from(from.as_linear_rgba_f32());
+    let to = Vec4::from(to.as_linear_rgba_f32());
+    let [r, g, b, a] = from.lerp(to, t).to_array();
+    Color::rgba_linear(r, g, b, a)
+}
+
+fn lerp_transform(from: &Transform, to: &Transform, t: f32) -> Transform {
+    Transform {
+        translation: from.translation.lerp(to.translation, t),
+        rotation: from.rotation.slerp(to.rotation, t),
+        scale: from.scale.lerp(to.scale, t),
+    }
+}
+
+impl LightDescription {
+    fn color(&self) -> Color {
+        let [r, g, b] = self.color;
+        Color::rgb(r, g, b)
+    }
+
+    pub fn light(&self) -> Light {
+        let shadows_enabled = self.shadows.is_some();
+        let (depth_bias, normal_bias) = match &self.shadows {
+            Some(shadows) => (shadows.depth_bias, shadows.normal_bias),
+            None => (0.0, 0.0),
+        };
+        match self.kind {
+            LightKind::Directional {
+                illuminance,
+                shadow_half_size,
+            } => Light::Directional(DirectionalLight {
+                color: self.color(),
+                illuminance,
+                shadow_projection: OrthographicProjection {
+                    left: -shadow_half_size,
+                    right: shadow_half_size,
+                    bottom: -shadow_half_size,
+                    top: shadow_half_size,
+                    near: -10.0 * shadow_half_size,
+                    far: 10.0 * shadow_half_size,
+                    ..default()
+                },
+                shadows_enabled,
+                shadow_depth_bias: depth_bias,
+                shadow_normal_bias: normal_bias,
+            }),
+            LightKind::Point {
+                intensity,
+                range,
+                radius,
+            } => Light::Point(PointLight {
+                color: self.color(),
+                intensity,
+                range,
+                radius,
+                shadows_enabled,
+                shadow_depth_bias: depth_bias,
+                shadow_normal_bias: normal_bias,
+            }),
+            LightKind::Spot {
+                intensity,
+                range,
+                radius,
+                inner_angle,
+                outer_angle,
+            } => Light::Spot(SpotLight {
+                color: self.color(),
+                intensity,
+                range,
+                radius,
+                inner_angle: inner_angle.to_radians(),
+                outer_angle: outer_angle.to_radians(),
+                shadows_enabled,
+                shadow_depth_bias: depth_bias,
+                shadow_normal_bias: normal_bias,
+            }),
+        }
+    }
+
+    /// Same light with its intensity at 0, used to fade lights in and out.
+    fn off(&self) -> Light {
+        let mut light = self.light();
+        match &mut light {
+            Light::Directional(light) => light.illuminance = 0.0,
+            Light::Point(light) => light.intensity = 0.0,
+            Light::Spot(light) => light.intensity = 0.0,
+        }
+        light
+    }
+
+    pub fn sun_driven(&self) -> Option<SunDriven> {
+        let intensity = match self.kind {
+            LightKind::Directional { illuminance, .. } => illuminance,
+            LightKind::Point { intensity, .. } | LightKind::Spot { intensity, .. } => {
+                if !self.follows_sun {
+                    return None;
+                }
+                intensity
+            }
+        };
+        let transform = Transform::from(&self.transform);
+        Some(SunDriven {
+            color: self.color(),
+            intensity,
+            translation: transform.translation,
+            rotation: transform.rotation,
+            bounce: self.bounce,
+        })
+    }
+
+    pub fn spawn(&self, commands: &mut Commands) -> Entity {
+        let transform = Transform::from(&self.transform);
+        let mut entity = match self.light() {
+            Light::Directional(directional_light) => commands.spawn(DirectionalLightBundle {
+                directional_light,
+                transform,
+                ..default()
+            }),
+            Light::Point(point_light) => commands.spawn(PointLightBundle {
+                point_light,
+                transform,
+                ..default()
+            }),
+            Light::Spot(spot_light) => commands.spawn(SpotLightBundle {
+                spot_light,
+                transform,
+                ..default()
+            }),
+        };
+        entity.insert((GrifLight, Name::new(self.name.clone())));
+        if let Some(sun_driven) = self.sun_driven() {
+            entity.insert(sun_driven);
+        }
+        entity.id()
+    }
+}
+
+#[derive(Default)]
+pub struct LightingPresetLoader;
+
+impl AssetLoader for LightingPresetLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, anyhow::Result<()>> {
+        Box::pin(async move {
+            let preset: LightingPreset = ron::de::from_bytes(bytes)?;
+            preset.check_names()?;
+            load_context.set_default_asset(LoadedAsset::new(preset));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lighting.ron"]
+    }
+}
+
+#[derive(Resource)]
+pub struct LightingRig {
+    /// Sorted by name.
+    presets: Vec<(String, Handle<LightingPreset>)>,
+    current: usize,
+}
+
+impl LightingRig {
+    /// Whether the current preset is done loading, or failed to. Its lights are spawned as soon
+    /// as it's loaded, a preset can also have no lights at all.
+    pub fn loaded(&self, asset_server: &AssetServer) -> bool {
+        self.presets.get(self.current).is_none_or(|(_, handle)| {
+            matches!(
+                asset_server.get_load_state(handle),
+                LoadState::Loaded | LoadState::Failed
+            )
+        })
+    }
+}
+
+fn load_lighting_presets(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Option<Res<LightingSettings>>,
+) {
+    let settings = settings.map(|s| s.clone()).unwrap_or_default();
+    let handles = match asset_server.load_folder(&settings.folder) {
+        Ok(handles) => handles,
+        Err(e) => {
+            error!(
+                "Failed to load the lighting presets from {}: {e}",
+                settings.folder
+            );
+            return;
+        }
+    };
+
+    let mut presets: Vec<_> = handles
+        .into_iter()
+        .filter_map(|handle| {
+            let path = asset_server.get_handle_path(&handle)?;
+            let file_name = path.path().file_name()?.to_str()?;
+            let name = file_name.strip_suffix(PRESET_EXTENSION)?.to_string();
+            Some((name, handle.typed()))
+        })
+        .collect();
+    presets.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let current = presets
+        .iter()
+        .position(|(name, _)| *name == settings.preset)
+        .unwrap_or_else(|| {
+            let names: Vec<_> = presets.iter().map(|(name, _)| name.as_str()).collect();
+            warn!(
+                "No lighting preset named {}, available presets: {}",
+                settings.preset,
+                names.join(", ")
+            );
+            0
+        });
+    if let Some((name, _)) = presets.get(current) {
+        println!("Lighting preset {name}");
+    }
+    commands.insert_resource(LightingRig { presets, current });
+}
+
+type AnyLight<'a> = (
+    Option<&'a DirectionalLight>,
+    Option<&'a PointLight>,
+    Option<&'a SpotLight>,
+);
+
+type RigLightQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Name,
+        AnyOf<(
+            &'static DirectionalLight,
+            &'static PointLight,
+            &'static SpotLight,
+        )>,
+        &'static Transform,
+        Option<&'static SunDriven>,
+    ),
+    With<GrifLight>,
+>;
+
+fn light_of((directional, point, spot): AnyLight) -> Light {
+    match (directional, point, spot) {
+        (Some(light), _, _) => Light::Directional(light.clone()),
+        (_, Some(light), _) => Light::Point(*light),
+        (_, _, Some(light)) => Light::Spot(*light),
+        _ => unreachable!("AnyOf matches at least one light"),
+    }
+}
+
+/// Spawns the rig once the preset is loaded and keeps it in sync when the file changes.
+fn sync_lighting_rig(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<LightingPreset>>,
+    presets: Res<Assets<LightingPreset>>,
+    rig: Option<Res<LightingRig>>,
+    lights: RigLightQuery,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    let Some(rig) = rig else {
+        return;
+    };
+    let Some((_, current)) = rig.presets.get(rig.current) else {
+        return;
+    };
+    for event in events.iter() {
+        let (AssetEvent::Created { handle } | AssetEvent::Modified { handle }) = event else {
+            continue;
+        };
+        if handle != current {
+            continue;
+        }
+        if let Some(preset) = presets.get(handle) {
+            sync_lights(&mut commands, preset, &lights);
+            if let Some(ambient) = preset.ambient_light() {
+                *ambient_light = ambient;
+            }
+            if let Some(color) = preset.clear_color() {
+                clear_color.0 = color;
+            }
+        }
+    }
+}
+
+/// Updates the lights in place, matching them by name. Lights that changed type are
+/// respawned, the ones that aren't in the preset anymore are despawned.
+fn sync_lights(commands: &mut Commands, preset: &LightingPreset, lights: &RigLightQuery) {
+    let mut existing: HashMap<&str, (Entity, LightType)> = HashMap::new();
+    for (entity, name, light, _, _) in lights.iter() {
+        existing.insert(name.as_str(), (entity, light_of(light).light_type()));
+    }
+
+    for description in &preset.lights {
+        let transform = Transform::from(&description.transform);
+        let light = description.light();
+        match existing.remove(description.name.as_str()) {
+            Some((entity, light_type)) if light_type == description.kind.light_type() => {
+                let mut entity = commands.entity(entity);
+                entity.insert(transform).remove::<LightFade>();
+                light.insert(&mut entity);
+                match description.sun_driven() {
+                    Some(sun_driven) => entity.insert(sun_driven),
+                    None => entity.remove::<SunDriven>(),
+                };
+            }
+            Some((entity, _)) => {
+                commands.entity(entity).despawn_recursive();
+                description.spawn(commands);
+            }
+            None => {
+                description.spawn(commands);
+            }
+        }
+    }
+
+    for (entity, _) in existing.into_values() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Moves a light from one preset to the next.
+#[derive(Component)]
+struct LightFade {
+    from: (Light, Transform, Option<SunDriven>),
+    to: (Light, Transform, Option<SunDriven>),
+    /// The light isn't in the next preset.
+    despawn: bool,
+}
+
+#[derive(Resource)]
+struct LightingTransition {
+    elapsed: f32,
+    duration: f32,
+    ambient: Option<(AmbientLight, AmbientLight)>,
+    clear_color: Option<(Color, Color)>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn cycle_lighting_preset(
+    mut commands: Commands,
+    key_input: Res<Input<KeyCode>>,
+    settings: Option<Res<LightingSettings>>,
+    presets: Res<Assets<LightingPreset>>,
+    rig: Option<ResMut<LightingRig>>,
+    lights: RigLightQuery,
+    ambient_light: Res<AmbientLight>,
+    clear_color: Res<ClearColor>,
+) {
+    let settings = settings.map(|s| s.clone()).unwrap_or_default();
+    let Some(mut rig) = rig else {
+        return;
+    };
+    if !key_input.just_pressed(settings.key_next) || rig.presets.is_empty() {
+        return;
+    }
+    rig.current = (rig.current + 1) % rig.presets.len();
+    let (name, handle) = &rig.presets[rig.current];
+    println!("Lighting preset {name}");
+    let Some(preset) = presets.get(handle) else {
+        return;
+    };
+
+    let mut existing = HashMap::new();
+    for (entity, name, light, transform, sun_driven) in lights.iter() {
+        existing.insert(
+            name.as_str(),
+            (entity, light_of(light), *transform, sun_driven.copied()),
+        );
+    }
+
+    for description in &preset.lights {
+        let to = (
+            description.light(),
+            Transform::from(&description.transform),
+            description.sun_driven(),
+        );
+        match existing.remove(description.name.as_str()) {
+            Some((entity, light, transform, sun_driven))
+                if light.light_type() == description.kind.light_type() =>
+            {
+                commands.entity(entity).insert(LightFade {
+                    from: (light, transform, sun_driven),
+                    to,
+                    despawn: false,
+                });
+            }
+            other => {
+                if let Some((entity, light, transform, sun_driven)) = other {
+                    fade_out(&mut commands, entity, light, transform, sun_driven);
+                }
+                let entity = description.spawn(&mut commands);
+                let off = description.off();
+                let mut entity = commands.entity(entity);
+                off.clone().insert(&mut entity);
+                entity.insert(LightFade {
+                    from: (
+                        off,
+                        to.1,
+                        to.2.map(|s| SunDriven {
+                            intensity: 0.0,
+                            ..s
+                        }),
+                    ),
+                    to,
+                    despawn: false,
+                });
+            }
+        }
+    }
+    for (entity, light, transform, sun_driven) in existing.into_values() {
+        fade_out(&mut commands, entity, light, transform, sun_driven);
+    }
+
+    commands.insert_resource(LightingTransition {
+        elapsed: 0.0,
+        duration: settings.transition,
+        ambient: preset.ambient_light().map(|to| (ambient_light.clone(), to)),
+        clear_color: preset.clear_color().map(|to| (clear_color.0, to)),
+    });
+}
+
+fn fade_out(
+    commands: &mut Commands,
+    entity: Entity,
+    light: Light,
+    transform: Transform,
+    sun_driven: Option<SunDriven>,
+) {
+    let mut off = light.clone();
+    *off.intensity_mut() = 0.0;
+    commands.entity(entity).insert(LightFade {
+        from: (light, transform, sun_driven),
+        to: (
+            off,
+            transform,
+            sun_driven.map(|s| SunDriven {
+                intensity: 0.0,
+                ..s
+            }),
+        ),
+        despawn: true,
+    });
+}
+
+fn fade_lighting(
+    mut commands: Commands,
+    time: Res<Time>,
+    transition: Option<ResMut<LightingTransition>>,
+    fading: Query<(Entity, &LightFade)>,
+    mut ambient_light: ResMut<AmbientLight>,
+    mut clear_color: ResMut<ClearColor>,
+) {
+    let Some(mut transition) = transition else {
+        return;
+    };
+    transition.elapsed += time.delta_seconds();
+    let t = if transition.duration > 0.0 {
+        (transition.elapsed / transition.duration).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    // Smoothstep
+    let t = t * t * (3.0 - 2.0 * t);
+
+    for (entity, fade) in &fading {
+        let (from_light, from_transform, from_sun) = &fade.from;
+        let (to_light, to_transform, to_sun) = &fade.to;
+        let mut entity = commands.entity(entity);
+        if t >= 1.0 {
+            if fade.despawn {
+                entity.despawn_recursive();
+                continue;
+            }
+            entity.remove::<LightFade>();
+        }
+        from_light.lerp(to_light, t).insert(&mut entity);
+        entity.insert(lerp_transform(from_transform, to_transform, t));
+        match (from_sun, to_sun) {
+            (Some(from), Some(to)) => {
+                entity.insert(SunDriven {
+                    color: lerp_color(from.color, to.color, t),
+                    intensity: lerp(from.intensity, to.intensity, t),
+                    translation: from.translation.lerp(to.translation, t),
+                    rotation: from.rotation.slerp(to.rotation, t),
+                    bounce: to.bounce,
+                });
+            }
+            (_, Some(to)) => {
+                entity.insert(*to);
+            }
+            (_, None) => {
+                entity.remove::<SunDriven>();
+            }
+        }
+    }
+
+    if let Some((from, to)) = &transition.ambient {
+        *ambient_light = AmbientLight {
+            color: lerp_color(from.color, to.color, t),
+            brightness: lerp(from.brightness, to.brightness, t),
+        };
+    }
+    if let Some((from, to)) = transition.clear_color {
+        clear_color.0 = lerp_color(from, to, t);
+    }
+    if t >= 1.0 {
+        commands.remove_resource::<LightingTransition>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shipped_presets_are_valid() {
+        for entry in std::fs::read_dir("assets/lighting").unwrap() {
+            let path = entry.unwrap().path();
+            let preset: LightingPreset = ron::from_str(&std::fs::read_to_string(&path).unwrap())
+                .unwrap_or_else(|e| panic!("{}: {e}", path.display()));
+            preset.check_names().unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_preset_loads_without_lights() {
+        let dir = std::env::temp_dir().join(format!("sponza_empty_preset_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lighting")).unwrap();
+        std::fs::write(dir.join("lighting/empty.lighting.ron"), "(lights: [])").unwrap();
+
+        let mut app = App::new();
+        app.add_plugins(MinimalPlugins)
+            .add_plugin(AssetPlugin {
+                asset_folder: dir.to_string_lossy().to_string(),
+                watch_for_changes: false,
+            })
+            .init_resource::<Input<KeyCode>>()
+            .init_resource::<AmbientLight>()
+            .init_resource::<ClearColor>()
+            .insert_resource(LightingSettings {
+                preset: "empty".into(),
+                ..default()
+            })
+            .add_plugin(LightingPlugin);
+        for _ in 0..500 {
+            app.update();
+            let asset_server = app.world.resource::<AssetServer>();
+            if app.world.resource::<LightingRig>().loaded(asset_server) {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        app.update();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let rig = app.world.resource::<LightingRig>();
+        let (name, handle) = &rig.presets[rig.current];
+        assert_eq!(name, "empty");
+        let asset_server = app.world.resource::<AssetServer>();
+        assert_eq!(asset_server.get_load_state(handle), LoadState::Loaded);
+        let mut lights = app.world.query_filtered::<(), With<GrifLight>>();
+        assert_eq!(lights.iter(&app.world).count(), 0);
+    }
+
+    #[test]
+    fn duplicate_names_are_rejected() {
+        let preset: LightingPreset = ron::from_str(
+            r#"(lights: [
+                (name: "Fill", kind: Point(intensity: 100.0, range: 5.0), color: (1.0, 1.0, 1.0)),
+                (name: "Fill", kind: Point(intensity: 200.0, range: 5.0), color: (1.0, 1.0, 1.0)),
+            ])"#,
+        )
+        .unwrap();
+        let error = preset.check_names().unwrap_err().to_string();
+        assert!(error.contains("\"Fill\""), "{error}");
+    }
+}
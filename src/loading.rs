@@ -0,0 +1,272 @@
+//! Tracks the scene loading in an app state: the packages get loaded and spawned, then fixed up
+//! and their mipmaps generated. A progress bar is shown until everything is ready.
+
+use bevy::{
+    asset::HandleId, ecs::system::SystemParam, prelude::*, scene::SceneInstance, utils::HashSet,
+};
+
+use crate::{
+    camera_controller::CameraController,
+    lighting::LightingRig,
+    mipmap_generator::{GetImages, MipmapTasks},
+    PostProcScene,
+};
+
+/// Frames everything has to stay loaded for before being ready, the mipmap tasks only get
+/// created once the materials are loaded. Assets that still show up after that send the app
+/// back to post processing.
+const SETTLE_FRAMES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    /// Loading the gltf files and spawning the scenes.
+    Loading,
+    /// Fixing up the spawned scenes and generating mipmaps.
+    PostProcessing,
+    /// The camera controller is only enabled once ready. Goes back to `PostProcessing` if
+    /// another material or one of their textures gets loaded.
+    Ready,
+}
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::Loading)
+            .init_resource::<LoadingProgress>()
+            .add_startup_system(spawn_loading_overlay)
+            .add_system(update_loading_state)
+            .add_system(update_loading_overlay.after(update_loading_state))
+            .add_system_set(SystemSet::on_enter(AppState::Ready).with_system(finish_loading));
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct LoadingProgress {
+    pub scenes: usize,
+    pub scenes_ready: usize,
+    /// Every image that had its mipmaps generated or is still waiting for them.
+    mipmaps: HashSet<HandleId>,
+    pub mipmaps_pending: usize,
+    settled_frames: u32,
+}
+
+impl LoadingProgress {
+    /// From 0 to 1, each scene and each mipmap task count the same.
+    pub fn fraction(&self) -> f32 {
+        let total = self.scenes + self.mipmaps.len();
+        let done = self.scenes_ready + self.mipmaps.len() - self.mipmaps_pending;
+        if total == 0 {
+            0.0
+        } else {
+            done as f32 / total as f32
+        }
+    }
+}
+
+#[derive(SystemParam)]
+struct SceneLoading<'w, 's> {
+    scene_spawner: Res<'w, SceneSpawner>,
+    scenes: Query<'w, 's, Option<&'static SceneInstance>, With<Handle<Scene>>>,
+    post_proc: Query<'w, 's, (), With<PostProcScene>>,
+    /// Missing when the lighting presets couldn't be loaded.
+    lighting: Option<Res<'w, LightingRig>>,
+    asset_server: Res<'w, AssetServer>,
+    mipmap_tasks: Option<Res<'w, MipmapTasks<StandardMaterial>>>,
+}
+
+/// Reads the asset events every frame, so only the ones sent since the last frame are seen.
+#[derive(SystemParam)]
+struct NewAssets<'w, 's> {
+    images: EventReader<'w, 's, AssetEvent<Image>>,
+    material_events: EventReader<'w, 's, AssetEvent<StandardMaterial>>,
+    materials: Res<'w, Assets<StandardMaterial>>,
+}
+
+impl<'w, 's> NewAssets<'w, 's> {
+    /// Whether a material or a texture used by one was created since the last frame. Other
+    /// images, like the font atlases of the overlays, aren't part of the scene.
+    fn scene_assets_created(&mut self) -> bool {
+        let images: HashSet<HandleId> = self
+            .images
+            .iter()
+            .filter_map(|event| match event {
+                AssetEvent::Created { handle } => Some(handle.id()),
+                _ => None,
+            })
+            .collect();
+        let materials = self
+            .material_events
+            .iter()
+            .filter(|event| matches!(event, AssetEvent::Created { .. }))
+            .count();
+        if materials > 0 || images.is_empty() {
+            return materials > 0;
+        }
+        self.materials.iter().any(|(_, material)| {
+            material
+                .get_images()
+                .into_iter()
+                .flatten()
+                .any(|image| images.contains(&image.id()))
+        })
+    }
+}
+
+impl<'w, 's> SceneLoading<'w, 's> {
+    fn scenes_ready(&self) -> usize {
+        self.scenes
+            .iter()
+            .filter(|instance| {
+                instance.is_some_and(|instance| self.scene_spawner.instance_is_ready(**instance))
+            })
+            .count()
+    }
+
+    fn post_processed(&self) -> bool {
+        self.post_proc.is_empty()
+            && self
+                .lighting
+                .as_ref()
+                .is_none_or(|rig| rig.loaded(&self.asset_server))
+    }
+}
+
+fn update_loading_state(
+    loading: SceneLoading,
+    mut new_assets: NewAssets,
+    mut progress: ResMut<LoadingProgress>,
+    mut state: ResMut<State<AppState>>,
+) {
+    progress.scenes = loading.scenes.iter().len();
+    progress.scenes_ready = loading.scenes_ready();
+    progress.mipmaps_pending = 0;
+    if let Some(tasks) = &loading.mipmap_tasks {
+        progress
+            .mipmaps
+            .extend(tasks.keys().map(|handle| handle.id()));
+        progress.mipmaps_pending = tasks.len();
+    }
+
+    let scenes_ready = progress.scenes > 0 && progress.scenes_ready == progress.scenes;
+    let assets_created = new_assets.scene_assets_created();
+    match state.current() {
+        AppState::Loading if scenes_ready => {
+            println!("Scene spawned, generating mipmaps");
+            let _ = state.set(AppState::PostProcessing);
+        }
+        AppState::PostProcessing => {
+            let done = scenes_ready && loading.post_processed() && progress.mipmaps_pending == 0;
+            progress.settled_frames = if done { progress.settled_frames + 1 } else { 0 };
+            if progress.settled_frames >= SETTLE_FRAMES {
+                let _ = state.set(AppState::Ready);
+            }
+        }
+        AppState::Ready => {
+            let done = scenes_ready && loading.post_processed() && progress.mipmaps_pending == 0;
+            if assets_created || !done {
+                println!("More assets got loaded, generating mipmaps");
+                progress.settled_frames = 0;
+                let _ = state.set(AppState::PostProcessing);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Root of the progress bar, despawned once ready.
+#[derive(Component)]
+struct LoadingOverlay;
+
+#[derive(Component)]
+struct LoadingBar;
+
+/// Window title from before the progress got added to it.
+#[derive(Resource)]
+struct WindowTitle(String);
+
+fn spawn_loading_overlay(mut commands: Commands, windows: Res<Windows>) {
+    // Headless apps render to an image, the overlay would end up in it
+    let Some(window) = windows.get_primary() else {
+        return;
+    };
+    commands.insert_resource(WindowTitle(window.title().to_string()));
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Percent(20.0),
+                        bottom: Val::Px(40.0),
+                        ..default()
+                    },
+                    size: Size::new(Val::Percent(60.0), Val::Px(12.0)),
+                    padding: UiRect::all(Val::Px(2.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.6).into(),
+                ..default()
+            },
+            LoadingOverlay,
+        ))
+        .with_children(|parent| {
+            parent.spawn((
+                NodeBundle {
+                    style: Style {
+                        size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                        ..default()
+                    },
+                    background_color: Color::rgb(0.9, 0.9, 0.9).into(),
+                    ..default()
+                },
+                LoadingBar,
+            ));
+        });
+}
+
+fn update_loading_overlay(
+    state: Res<State<AppState>>,
+    progress: Res<LoadingProgress>,
+    title: Option<Res<WindowTitle>>,
+    mut windows: ResMut<Windows>,
+    mut bars: Query<&mut Style, With<LoadingBar>>,
+) {
+    if *state.current() == AppState::Ready || !progress.is_changed() {
+        return;
+    }
+    let percent = progress.fraction() * 100.0;
+    for mut style in &mut bars {
+        style.size.width = Val::Percent(percent);
+    }
+    if let (Some(title), Some(window)) = (title, windows.get_primary_mut()) {
+        let stage = match state.current() {
+            AppState::Loading => "loading scenes",
+            _ => "generating mipmaps",
+        };
+        let progress_title = format!("{} - {stage} {percent:.0}%", title.0);
+        if window.title() != progress_title {
+            window.set_title(progress_title);
+        }
+    }
+}
+
+fn finish_loading(
+    mut commands: Commands,
+    time: Res<Time>,
+    title: Option<Res<WindowTitle>>,
+    mut windows: ResMut<Windows>,
+    overlays: Query<Entity, With<LoadingOverlay>>,
+    mut controllers: Query<&mut CameraController>,
+) {
+    println!("Ready after {:.1}s", time.elapsed_seconds());
+    for entity in &overlays {
+        commands.entity(entity).despawn_recursive();
+    }
+    if let (Some(title), Some(window)) = (title, windows.get_primary_mut()) {
+        window.set_title(title.0.clone());
+    }
+    for mut controller in &mut controllers {
+        controller.enabled = true;
+    }
+}
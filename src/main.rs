@@ -1,48 +1,210 @@
-use std::{f32::consts::PI, num::NonZeroU8};
+use std::{num::NonZeroU8, time::Duration};
 
+mod bcn;
+mod bcn_decode;
+mod bench;
 mod camera_controller;
+mod cli;
+mod gltf_json;
+mod hierarchy;
+mod ktx2_writer;
+mod lighting;
+mod loading;
+mod materials;
 mod mipmap_generator;
+mod name_pattern;
+mod scene_manifest;
+mod scene_objects;
+mod screenshot;
+mod stats;
+mod texture_manifest;
+mod time_of_day;
+mod validate;
 
 use bevy::{
+    app::{ScheduleRunnerPlugin, ScheduleRunnerSettings},
+    asset::HandleId,
     core_pipeline::{bloom::BloomSettings, fxaa::Fxaa},
+    gltf::Gltf,
     prelude::*,
+    utils::{HashMap, HashSet},
+    winit::WinitPlugin,
 };
 use camera_controller::{CameraController, CameraControllerPlugin};
+use clap::Parser;
+use lighting::{GrifLight, LightingPlugin, LightingSettings};
+use loading::LoadingPlugin;
 use mipmap_generator::{generate_mipmaps, MipmapGeneratorPlugin, MipmapGeneratorSettings};
+use scene_objects::{GltfCamera, GltfLight, SceneObjects, SceneObjectsPlugin};
+use time_of_day::{TimeOfDay, TimeOfDayPlugin};
 
-use crate::convert::{change_gltf_to_use_ktx2, convert_images_to_ktx2};
+use crate::{
+    bench::{BenchPlugin, BenchResult},
+    cli::{Cli, Command, ViewArgs},
+    convert::{change_gltf_to_use_ktx2, convert_images_to_ktx2, revert_gltf_to_png},
+    hierarchy::{descendants, Visit},
+    materials::dump_materials,
+    scene_manifest::{MaterialOverrides, PostProcessRules, SceneManifest},
+    screenshot::{ScreenshotPlugin, ScreenshotResult},
+    stats::{StatsOverlay, StatsOverlayPlugin, StatsReportPlugin, StatsResult, StatsTimeout},
+    validate::validate_assets,
+};
 
 mod convert;
 
 pub fn main() {
-    let args = &mut std::env::args();
-    args.next();
-    if let Some(arg) = &args.next() {
-        if arg == "--convert" {
+    let cli = Cli::parse();
+    let result = match cli.command.unwrap_or_default() {
+        Command::View(args) => args.assets.load_scene().and_then(|scene| {
+            validate_assets(&args.assets.asset_root, &scene)?;
+            viewer_app(&args, scene, false)?.run();
+            Ok(())
+        }),
+        Command::Convert(args) => args.assets.load_scene().and_then(|scene| {
+            let settings = args.settings();
+            let asset_root = &args.assets.asset_root;
             println!("This will take a few minutes");
-            convert_images_to_ktx2();
-            change_gltf_to_use_ktx2();
-        }
+            // Update the gltf files even if some textures failed, only converted images get changed
+            let converted = convert_images_to_ktx2(asset_root, &scene, &settings);
+            let updated = change_gltf_to_use_ktx2(asset_root, &scene, &settings);
+            converted.and(updated)
+        }),
+        Command::Revert(args) => args.assets.load_scene().and_then(|scene| {
+            revert_gltf_to_png(&args.assets.asset_root, &scene, args.delete_ktx2)
+        }),
+        Command::Bench(args) => args.view.assets.load_scene().and_then(|scene| {
+            let settings = args.settings()?;
+            validate_assets(&args.view.assets.asset_root, &scene)?;
+            let mut app = viewer_app(&args.view, scene, false)?;
+            app.insert_resource(settings).add_plugin(BenchPlugin);
+            app.run();
+            match app.world.remove_resource::<BenchResult>() {
+                Some(BenchResult(result)) => result,
+                None => Err(anyhow::anyhow!("Exited before the end of the benchmark")),
+            }
+        }),
+        Command::Screenshot(args) => args.view.assets.load_scene().and_then(|scene| {
+            let settings = args.settings()?;
+            validate_assets(&args.view.assets.asset_root, &scene)?;
+            let mut app = viewer_app(&args.view, scene, true)?;
+            app.insert_resource(settings).add_plugin(ScreenshotPlugin);
+            app.run();
+            match app.world.remove_resource::<ScreenshotResult>() {
+                Some(ScreenshotResult(result)) => result,
+                None => Err(anyhow::anyhow!("Exited before taking the screenshot")),
+            }
+        }),
+        Command::Stats(args) => args.view.assets.load_scene().and_then(|scene| {
+            validate_assets(&args.view.assets.asset_root, &scene)?;
+            let mut app = viewer_app(&args.view, scene, true)?;
+            app.insert_resource(StatsTimeout(args.timeout))
+                .add_plugin(StatsReportPlugin);
+            app.run();
+            match app.world.remove_resource::<StatsResult>() {
+                Some(StatsResult(result)) => result,
+                None => Err(anyhow::anyhow!("Exited before printing the stats")),
+            }
+        }),
+        Command::Validate(args) => args
+            .assets
+            .load_scene()
+            .and_then(|scene| validate_assets(&args.assets.asset_root, &scene)),
+        Command::Materials(args) => args.assets.load_scene().and_then(|scene| {
+            let overrides = scene.load_material_overrides()?;
+            dump_materials(&args.assets.asset_root, &scene, &overrides)
+        }),
+    };
+
+    if let Err(e) = result {
+        eprintln!("{e:#}");
+        std::process::exit(1);
     }
+}
+
+/// Without a window when `headless` is set, the app then needs a camera that renders to an image.
+fn viewer_app(args: &ViewArgs, mut scene: SceneManifest, headless: bool) -> anyhow::Result<App> {
+    // Use the same folder as the converter, which resolves relative paths against the working directory
+    let asset_root = &args.assets.asset_root;
+    let asset_folder = asset_root
+        .canonicalize()
+        .unwrap_or_else(|_| asset_root.clone());
 
+    let material_overrides = scene.load_material_overrides()?;
     let mut app = App::new();
 
-    app.insert_resource(Msaa { samples: 1 })
+    let time_of_day = TimeOfDay {
+        enabled: args.hour.is_some(),
+        hour: args.hour.unwrap_or(12.0),
+        ..default()
+    };
+    let lighting = LightingSettings {
+        preset: args.lighting.clone(),
+        ..default()
+    };
+    let scene_objects = SceneObjects {
+        gltf_lights: args.gltf_lights,
+        light_units: args.gltf_light_units,
+        ..default()
+    };
+    if !headless {
+        time_of_day.print_controls();
+        lighting.print_controls();
+        scene_objects.print_controls();
+        StatsOverlay::default().print_controls();
+    }
+    for package in &mut scene.packages {
+        package.post_process.strip_lights &= !args.gltf_lights;
+        package.post_process.strip_cameras &= !args.gltf_cameras;
+    }
+
+    let plugins = DefaultPlugins
+        .set(WindowPlugin {
+            window: WindowDescriptor {
+                width: args.window.width,
+                height: args.window.height,
+                ..default()
+            },
+            add_primary_window: !headless,
+            exit_on_all_closed: !headless,
+            ..default()
+        })
+        .set(AssetPlugin {
+            asset_folder: asset_folder.to_string_lossy().to_string(),
+            // Reloads the lighting presets when they are edited
+            watch_for_changes: !headless,
+        });
+
+    // Read by the render plugin when it creates the device
+    app.insert_resource(args.wgpu_settings())
+        .insert_resource(scene)
+        .insert_resource(time_of_day)
+        .insert_resource(lighting)
+        .insert_resource(scene_objects)
+        .insert_resource(material_overrides)
+        .insert_resource(Msaa { samples: 1 })
         .insert_resource(ClearColor(Color::rgb(1.75, 1.9, 1.99)))
         .insert_resource(AmbientLight {
             color: Color::rgb(1.0, 1.0, 1.0),
             brightness: 0.02,
-        })
-        .add_plugins(DefaultPlugins.set(WindowPlugin {
-            window: WindowDescriptor { ..default() },
-            ..default()
-        }))
-        //.add_plugin(LogDiagnosticsPlugin::default())
-        //.add_plugin(FrameTimeDiagnosticsPlugin::default())
-        .add_plugin(CameraControllerPlugin)
+        });
+    if headless {
+        // winit needs a display, run the schedule in a loop instead
+        app.insert_resource(ScheduleRunnerSettings::run_loop(Duration::ZERO))
+            .add_plugins(plugins.disable::<WinitPlugin>())
+            .add_plugin(ScheduleRunnerPlugin);
+    } else {
+        app.add_plugins(plugins).add_plugin(StatsOverlayPlugin);
+    }
+
+    app.add_plugin(CameraControllerPlugin)
+        .add_plugin(LoadingPlugin)
+        .add_plugin(LightingPlugin)
+        .add_plugin(TimeOfDayPlugin)
+        .add_plugin(SceneObjectsPlugin)
         // Generating mipmaps takes a minute
         .insert_resource(MipmapGeneratorSettings {
             anisotropic_filtering: NonZeroU8::new(16),
+            specular_antialiasing: true,
             ..default()
         })
         .add_plugin(MipmapGeneratorPlugin)
@@ -51,153 +213,39 @@ pub fn main() {
         .add_startup_system(setup)
         .add_system(proc_scene);
 
-    app.run();
+    Ok(app)
 }
 
+/// Marks a scene root whose materials, lights and cameras still need to be fixed up by `proc_scene`.
 #[derive(Component)]
-pub struct PostProcScene;
-
-#[derive(Component)]
-pub struct GrifLight;
-
-pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    println!("Loading models, generating mipmaps");
-
-    // sponza
-    commands
-        .spawn(SceneBundle {
-            scene: asset_server.load("main_sponza/NewSponza_Main_glTF_002.gltf#Scene0"),
-            ..default()
-        })
-        .insert(PostProcScene);
-
-    // curtains
-    commands
-        .spawn(SceneBundle {
-            scene: asset_server.load("PKG_A_Curtains/NewSponza_Curtains_glTF.gltf#Scene0"),
-            ..default()
-        })
-        .insert(PostProcScene);
-
-    // Sun
-    const HALF_SIZE: f32 = 20.0;
-    commands
-        .spawn(DirectionalLightBundle {
-            transform: Transform::from_rotation(Quat::from_euler(
-                EulerRot::XYZ,
-                PI * -0.43,
-                PI * -0.08,
-                0.0,
-            )),
-            directional_light: DirectionalLight {
-                color: Color::rgb(1.0, 1.0, 0.99),
-                illuminance: 400000.0,
-                shadow_projection: OrthographicProjection {
-                    left: -HALF_SIZE,
-                    right: HALF_SIZE,
-                    bottom: -HALF_SIZE,
-                    top: HALF_SIZE,
-                    near: -10.0 * HALF_SIZE,
-                    far: 10.0 * HALF_SIZE,
-                    ..default()
-                },
-                shadows_enabled: true,
-                shadow_depth_bias: 0.3,
-                shadow_normal_bias: 0.7,
-            },
-            ..default()
-        })
-        .insert(GrifLight);
-
-    // Sun Refl
-    commands
-        .spawn(SpotLightBundle {
-            transform: Transform::from_xyz(2.0, -0.0, -2.0)
-                .looking_at(Vec3::new(0.0, 999.0, 0.0), Vec3::X),
-            spot_light: SpotLight {
-                range: 15.0,
-                intensity: 1000.0,
-                color: Color::rgb(1.0, 0.97, 0.85),
-                shadows_enabled: false,
-                inner_angle: PI * 0.4,
-                outer_angle: PI * 0.5,
-                ..default()
-            },
-            ..default()
-        })
-        .insert(GrifLight);
-
-    // Sun refl 2nd bounce / misc bounces
-    commands
-        .spawn(SpotLightBundle {
-            transform: Transform::from_xyz(2.0, 5.5, -2.0)
-                .looking_at(Vec3::new(0.0, -999.0, 0.0), Vec3::X),
-            spot_light: SpotLight {
-                range: 13.0,
-                intensity: 800.0,
-                color: Color::rgb(1.0, 0.97, 0.85),
-                shadows_enabled: false,
-                inner_angle: PI * 0.3,
-                outer_angle: PI * 0.4,
-                ..default()
-            },
-            ..default()
-        })
-        .insert(GrifLight);
+pub struct PostProcScene {
+    /// The materials are matched by the name they have in the gltf file.
+    pub gltf: Handle<Gltf>,
+    pub rules: PostProcessRules,
+}
 
-    // sky
-    // seems to be making blocky artifacts. Even if it's the only light.
-    commands
-        .spawn(PointLightBundle {
-            point_light: PointLight {
-                color: Color::rgb(0.8, 0.9, 0.97),
-                intensity: 100000.0,
-                shadows_enabled: false,
-                range: 24.0,
-                radius: 3.0,
-                ..default()
-            },
-            transform: Transform::from_xyz(0.0, 30.0, 0.0),
-            ..default()
-        })
-        .insert(GrifLight);
+pub fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    scene_manifest: Res<SceneManifest>,
+) {
+    println!("Loading models");
 
-    // sky refl
-    commands
-        .spawn(SpotLightBundle {
-            transform: Transform::from_xyz(0.0, -2.0, 0.0)
-                .looking_at(Vec3::new(0.0, 999.0, 0.0), Vec3::X),
-            spot_light: SpotLight {
-                range: 11.0,
-                intensity: 300.0,
-                color: Color::rgb(0.8, 0.9, 0.97),
-                shadows_enabled: false,
-                inner_angle: PI * 0.46,
-                outer_angle: PI * 0.49,
+    for package in &scene_manifest.packages {
+        commands.spawn((
+            SceneBundle {
+                scene: asset_server.load(package.scene_asset_path()),
+                transform: (&package.transform).into(),
                 ..default()
             },
-            ..default()
-        })
-        .insert(GrifLight);
-
-    // sky low
-    commands
-        .spawn(SpotLightBundle {
-            transform: Transform::from_xyz(3.0, 2.0, 0.0)
-                .looking_at(Vec3::new(0.0, -999.0, 0.0), Vec3::X),
-            spot_light: SpotLight {
-                range: 12.0,
-                radius: 0.0,
-                intensity: 1800.0,
-                color: Color::rgb(0.8, 0.9, 0.95),
-                shadows_enabled: false,
-                inner_angle: PI * 0.34,
-                outer_angle: PI * 0.5,
-                ..default()
+            Name::new(package.name.clone()),
+            // Also marks the lights and cameras that aren't stripped
+            PostProcScene {
+                gltf: asset_server.load(package.gltf_asset_path()),
+                rules: package.post_process.clone(),
             },
-            ..default()
-        })
-        .insert(GrifLight);
+        ));
+    }
 
     // Camera
     commands
@@ -224,29 +272,25 @@ pub fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 intensity: 0.01,
             },
         ))
-        .insert(CameraController::default().print_controls())
+        // Enabled once the scene is loaded
+        .insert(
+            CameraController {
+                enabled: false,
+                ..default()
+            }
+            .print_controls(),
+        )
         .insert(Fxaa::default());
 }
 
-pub fn all_children<F: FnMut(Entity)>(
-    children: &Children,
-    children_query: &Query<&Children>,
-    closure: &mut F,
-) {
-    for child in children {
-        if let Ok(children) = children_query.get(*child) {
-            all_children(children, children_query, closure);
-        }
-        closure(*child);
-    }
-}
-
-#[allow(clippy::type_complexity)]
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn proc_scene(
     mut commands: Commands,
-    flip_normals_query: Query<Entity, With<PostProcScene>>,
+    post_proc_query: Query<(Entity, &PostProcScene)>,
     children_query: Query<&Children>,
-    has_std_mat: Query<&Handle<StandardMaterial>>,
+    mut has_std_mat: Query<&mut Handle<StandardMaterial>>,
+    gltfs: Res<Assets<Gltf>>,
+    overrides: Res<MaterialOverrides>,
     mut materials: ResMut<Assets<StandardMaterial>>,
     lights: Query<
         Entity,
@@ -257,27 +301,97 @@ pub fn proc_scene(
     >,
     cameras: Query<Entity, With<Camera>>,
 ) {
-    for entity in flip_normals_query.iter() {
-        if let Ok(children) = children_query.get(entity) {
-            all_children(children, &children_query, &mut |entity| {
-                // Sponza needs flipped normals
-                if let Ok(mat_h) = has_std_mat.get(entity) {
-                    if let Some(mat) = materials.get_mut(mat_h) {
-                        mat.flip_normal_map_y = true;
-                    }
+    for (entity, post_proc) in post_proc_query.iter() {
+        // The scene hasn't been spawned yet
+        if !children_query.contains(entity) {
+            continue;
+        }
+        let Some(gltf) = gltfs.get(&post_proc.gltf) else {
+            continue;
+        };
+        let rules = &post_proc.rules;
+        let material_names: HashMap<_, _> = gltf
+            .named_materials
+            .iter()
+            .map(|(name, handle)| (handle.id(), name.as_str()))
+            .collect();
+        let replacements = material_replacements(gltf, rules);
+
+        // Materials are shared between meshes, collect them so each one is only fixed once
+        let mut scene_materials = HashSet::new();
+        let mut descendants = descendants(entity, &children_query);
+        while let Some(Visit { entity, .. }) = descendants.next() {
+            if let Ok(mut mat_h) = has_std_mat.get_mut(entity) {
+                if let Some(replacement) = replacements.get(&mat_h.id()) {
+                    *mat_h = replacement.clone();
                 }
+                scene_materials.insert(mat_h.clone());
+            }
 
-                // Sponza has a bunch of lights by default
-                if lights.get(entity).is_ok() {
+            // Sponza has a bunch of lights by default
+            if lights.get(entity).is_ok() {
+                if rules.strip_lights {
                     commands.entity(entity).despawn_recursive();
+                    descendants.skip_children();
+                } else {
+                    commands.entity(entity).insert(GltfLight);
                 }
+            }
 
-                // Sponza has a bunch of cameras by default
-                if cameras.get(entity).is_ok() {
+            // Sponza has a bunch of cameras by default
+            if cameras.get(entity).is_ok() {
+                if rules.strip_cameras {
                     commands.entity(entity).despawn_recursive();
+                    descendants.skip_children();
+                } else {
+                    commands.entity(entity).insert(GltfCamera);
                 }
-            });
-            commands.entity(entity).remove::<PostProcScene>();
+            }
+        }
+
+        for mat_h in &scene_materials {
+            let name = material_names.get(&mat_h.id()).copied().unwrap_or_default();
+            if let Some(mat) = materials.get_mut(mat_h) {
+                fix_material(mat, name, rules, &overrides);
+            }
+        }
+        commands.entity(entity).remove::<PostProcScene>();
+    }
+}
+
+/// Maps the materials matching a replacement rule to the material replacing them.
+fn material_replacements(
+    gltf: &Gltf,
+    rules: &PostProcessRules,
+) -> HashMap<HandleId, Handle<StandardMaterial>> {
+    let mut replacements = HashMap::new();
+    for replacement in &rules.replace_materials {
+        let Some(with) = gltf.named_materials.get(&replacement.with) else {
+            warn!(
+                "Can't replace materials with {}, it doesn't exist",
+                replacement.with
+            );
+            continue;
+        };
+        for (name, handle) in &gltf.named_materials {
+            if replacement.name.matches(name) && handle != with {
+                replacements.insert(handle.id(), with.clone());
+            }
         }
     }
+    replacements
+}
+
+fn fix_material(
+    mat: &mut StandardMaterial,
+    name: &str,
+    rules: &PostProcessRules,
+    overrides: &MaterialOverrides,
+) {
+    if rules.flip_normal_map_y {
+        mat.flip_normal_map_y = true;
+    }
+    for rule in rules.materials.iter().chain(&overrides.materials) {
+        rule.apply(name, mat);
+    }
 }
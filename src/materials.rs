@@ -0,0 +1,116 @@
+//! Lists the materials of every package with their factors and textures, read from the gltf files.
+
+use std::path::Path;
+
+use serde_json::Value;
+
+use crate::{
+    gltf_json::GltfDocument,
+    scene_manifest::{MaterialOverrides, MaterialRule, SceneManifest},
+};
+
+pub fn dump_materials(
+    asset_root: &Path,
+    scene: &SceneManifest,
+    overrides: &MaterialOverrides,
+) -> anyhow::Result<()> {
+    for package in &scene.packages {
+        let gltf = GltfDocument::load(package.gltf_path(asset_root))?;
+        let materials = gltf.materials();
+        println!("{}: {} materials", package.name, materials.len());
+        for (i, material) in materials.iter().enumerate() {
+            let name = material
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or_default();
+            let alpha_mode = material
+                .get("alphaMode")
+                .and_then(Value::as_str)
+                .unwrap_or("OPAQUE");
+            let opaque = alpha_mode == "OPAQUE";
+
+            if name.is_empty() {
+                println!("  material {i}");
+            } else {
+                println!("  {name}");
+            }
+            let mut alpha = alpha_mode.to_string();
+            if let Some(cutoff) = material.get("alphaCutoff") {
+                alpha += &format!(" {cutoff}");
+            }
+            let double_sided = material
+                .get("doubleSided")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let unlit = material
+                .pointer("/extensions/KHR_materials_unlit")
+                .is_some();
+            println!("    alpha: {alpha}, double sided: {double_sided}, unlit: {unlit}");
+
+            let pbr = material.get("pbrMetallicRoughness");
+            let factor = |pointer: &str, default: &str| {
+                pbr.and_then(|pbr| pbr.pointer(pointer))
+                    .map_or(default.to_string(), Value::to_string)
+            };
+            println!(
+                "    base color: {}{}",
+                factor("/baseColorFactor", "[1,1,1,1]"),
+                texture(&gltf, pbr, "/baseColorTexture"),
+            );
+            println!(
+                "    metallic: {}, roughness: {}{}",
+                factor("/metallicFactor", "1"),
+                factor("/roughnessFactor", "1"),
+                texture(&gltf, pbr, "/metallicRoughnessTexture"),
+            );
+            let emissive = material
+                .get("emissiveFactor")
+                .map_or("[0,0,0]".to_string(), Value::to_string);
+            let strength = material
+                .pointer("/extensions/KHR_materials_emissive_strength/emissiveStrength")
+                .map(|strength| format!(" x {strength}"))
+                .unwrap_or_default();
+            println!(
+                "    emissive: {emissive}{strength}{}",
+                texture(&gltf, Some(material), "/emissiveTexture"),
+            );
+            for (label, pointer) in [
+                ("normal", "/normalTexture"),
+                ("occlusion", "/occlusionTexture"),
+            ] {
+                let texture = texture(&gltf, Some(material), pointer);
+                if !texture.is_empty() {
+                    println!("    {label}:{texture}");
+                }
+            }
+
+            let rules = matching(&package.post_process.materials, name, opaque);
+            if !rules.is_empty() {
+                println!("    package rules: {}", rules.join(", "));
+            }
+            let rules = matching(&overrides.materials, name, opaque);
+            if !rules.is_empty() {
+                println!("    overrides: {}", rules.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Uri of the texture referenced at `pointer`, with a leading space, or nothing.
+fn texture(gltf: &GltfDocument, parent: Option<&Value>, pointer: &str) -> String {
+    parent
+        .and_then(|parent| parent.pointer(&format!("{pointer}/index")))
+        .and_then(Value::as_u64)
+        .and_then(|index| gltf.texture_uri(index as usize))
+        .map(|uri| format!(" {uri}"))
+        .unwrap_or_default()
+}
+
+fn matching(rules: &[MaterialRule], name: &str, opaque: bool) -> Vec<String> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(name, opaque))
+        .map(|rule| rule.name.to_string())
+        .collect()
+}
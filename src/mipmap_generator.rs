@@ -1,9 +1,10 @@
 // Copied from https://github.com/DGriffin91/bevy_mod_mipmap_generator
 
 use anyhow::anyhow;
-use std::num::NonZeroU8;
+use std::{mem::discriminant, num::NonZeroU8};
 
 use bevy::{
+    asset::HandleId,
     prelude::*,
     render::{
         render_resource::{Extent3d, SamplerDescriptor, TextureDimension, TextureFormat},
@@ -13,7 +14,16 @@ use bevy::{
     utils::HashMap,
 };
 use futures_lite::future;
-use image::{imageops::FilterType, DynamicImage, ImageBuffer};
+use half::f16;
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, ImageBuffer, LumaA, Rgba32FImage,
+};
+
+use crate::{
+    bcn::{compress_bcn, BcnFormat},
+    bcn_decode::decompress,
+};
 
 #[derive(Resource, Deref)]
 pub struct DefaultSampler(SamplerDescriptor<'static>);
@@ -24,6 +34,12 @@ pub struct MipmapGeneratorSettings {
     pub anisotropic_filtering: Option<NonZeroU8>,
     pub filter_type: FilterType,
     pub minimum_mip_resolution: u32,
+    /// Filter sRGB textures in linear space, like the GPU blends them when sampling.
+    /// Filtering the encoded values darkens the lower mips.
+    pub linear_srgb_filtering: bool,
+    /// Raise the roughness in the mips of metallic roughness textures where the normals of the
+    /// material's normal map average out, so the highlights don't shimmer at a distance.
+    pub specular_antialiasing: bool,
 }
 
 ///Mipmaps will not be generated for materials found on entities that also have the `NoMipmapGeneration` component.
@@ -37,6 +53,8 @@ impl Default for MipmapGeneratorSettings {
             anisotropic_filtering: NonZeroU8::new(8),
             filter_type: FilterType::Triangle,
             minimum_mip_resolution: 2,
+            linear_srgb_filtering: true,
+            specular_antialiasing: false,
         }
     }
 }
@@ -67,6 +85,7 @@ pub fn generate_mipmaps<M: Material + GetImages>(
     default_sampler: Res<DefaultSampler>,
     settings: Res<MipmapGeneratorSettings>,
     mut tasks_res: Option<ResMut<MipmapTasks<M>>>,
+    mut roles: Local<HashMap<HandleId, TextureRole>>,
 ) {
     let mut new_tasks = MipmapTasks(HashMap::new());
 
@@ -90,10 +109,45 @@ pub fn generate_mipmaps<M: Material + GetImages>(
         // get_mut(material_h) here so we see the filtering right away
         // and even if mipmaps aren't made, we still get the filtering
         if let Some(material) = materials.get_mut(material_h) {
+            let mut material_images = Vec::new();
             for image_h in material.get_images().into_iter().flatten() {
+                // An image in several slots only gets one role
+                if material_images.contains(&image_h) {
+                    continue;
+                }
+                material_images.push(image_h);
+                let role = material.texture_role(image_h);
+                // The mips are only generated once, filtered for the first role the image had
+                if let Some(first_role) = roles.get(&image_h.id()) {
+                    if discriminant(first_role) != discriminant(&role) {
+                        warn!(
+                            "{image_h:?} is used as {} and {}, its mips are filtered as {}",
+                            first_role.name(),
+                            role.name(),
+                            first_role.name()
+                        );
+                    } else if let (
+                        TextureRole::MetallicRoughness { normal_map: first },
+                        TextureRole::MetallicRoughness { normal_map },
+                    ) = (first_role, &role)
+                    {
+                        if settings.specular_antialiasing && first != normal_map {
+                            warn!(
+                                "{image_h:?} is shared by materials with different normal maps, \
+                                 its roughness is only adjusted for {first:?}"
+                            );
+                        }
+                    }
+                }
                 if tasks.contains_key(image_h) {
                     continue; //There is already a task for this image
                 }
+                let normal_map = match &role {
+                    TextureRole::MetallicRoughness {
+                        normal_map: Some(normal_map),
+                    } if settings.specular_antialiasing => images.get(normal_map).cloned(),
+                    _ => None,
+                };
                 if let Some(image) = images.get_mut(image_h) {
                     let mut descriptor = match image.sampler_descriptor.clone() {
                         ImageSampler::Default => (*default_sampler).clone(),
@@ -105,10 +159,16 @@ pub fn generate_mipmaps<M: Material + GetImages>(
                         && check_image_compatible(image).is_ok()
                     {
                         dbg!("CONV");
+                        roles.insert(image_h.id(), role.clone());
                         let mut image = image.clone();
                         let settings = settings.clone();
                         let task = thread_pool.spawn(async move {
-                            match generate_mips_texture(&mut image, &settings.clone()) {
+                            match generate_mips_texture(
+                                &mut image,
+                                &role,
+                                normal_map.as_ref(),
+                                &settings.clone(),
+                            ) {
                                 Ok(_) => (),
                                 Err(e) => warn!("{}", e),
                             }
@@ -144,19 +204,29 @@ pub fn generate_mipmaps<M: Material + GetImages>(
     }
 }
 
+/// `normal_map` is only used by metallic roughness textures, for the specular antialiasing.
 pub fn generate_mips_texture(
     image: &mut Image,
+    role: &TextureRole,
+    normal_map: Option<&Image>,
     settings: &MipmapGeneratorSettings,
 ) -> anyhow::Result<()> {
     check_image_compatible(image)?;
+    if let Some(format) = bcn_format(image.texture_descriptor.format) {
+        return generate_mips_compressed(image, format, role, normal_map, settings);
+    }
     match try_into_dynamic(image.clone()) {
         Ok(mut dyn_image) => {
+            let format = image.texture_descriptor.format;
+            let source = MipSource::new(&dyn_image, format, role, normal_map, settings);
             let (mip_level_count, image_data) = generate_mips(
                 &mut dyn_image,
+                format,
                 settings.minimum_mip_resolution,
                 u32::MAX,
                 settings.filter_type,
-            );
+                source,
+            )?;
             image.texture_descriptor.mip_level_count = mip_level_count;
             image.data = image_data;
             Ok(())
@@ -165,16 +235,61 @@ pub fn generate_mips_texture(
     }
 }
 
-/// Returns the number of mip levels, and a vec of bytes containing the image data.
+/// Decodes the first level, generates the mips from it and encodes them back to `format`.
+/// The first level is kept as it was loaded so it doesn't lose any more quality, unless
+/// [`MipSource::first_level`] changes it.
+fn generate_mips_compressed(
+    image: &mut Image,
+    format: BcnFormat,
+    role: &TextureRole,
+    normal_map: Option<&Image>,
+    settings: &MipmapGeneratorSettings,
+) -> anyhow::Result<()> {
+    let size = image.texture_descriptor.size;
+    let mut dyn_image = decompress(&image.data, size.width, size.height, format)?;
+    let mut source = MipSource::new(
+        &dyn_image,
+        image.texture_descriptor.format,
+        role,
+        normal_map,
+        settings,
+    );
+    if source.first_level(&mut dyn_image, settings.filter_type) {
+        image.data = compress_bcn(&dyn_image, format);
+    } else {
+        let first_level_bytes =
+            (size.width.div_ceil(4) * size.height.div_ceil(4)) as usize * format.block_bytes();
+        image.data.truncate(first_level_bytes);
+    }
+
+    let minimum_mip_resolution = settings.minimum_mip_resolution.max(1);
+    let mut mip_level_count = 1;
+    let (mut width, mut height) = (size.width, size.height);
+    while width / 2 >= minimum_mip_resolution && height / 2 >= minimum_mip_resolution {
+        width /= 2;
+        height /= 2;
+        source.downsample(&mut dyn_image, width, height, settings.filter_type);
+        image.data.append(&mut compress_bcn(&dyn_image, format));
+        mip_level_count += 1;
+    }
+    image.texture_descriptor.mip_level_count = mip_level_count;
+    Ok(())
+}
+
+/// Returns the number of mip levels, and a vec of bytes containing the image data in `format`.
 /// The `max_mip_count` includes the first input mip level. So setting this to 2 will
 /// result in a single additional mip level being generated, for a total of 2 levels.
+/// The levels are filtered from `source`, see [`MipSource::new`].
 pub fn generate_mips(
     dyn_image: &mut DynamicImage,
+    format: TextureFormat,
     minimum_mip_resolution: u32,
     max_mip_count: u32,
     filter_type: FilterType,
-) -> (u32, Vec<u8>) {
-    let mut image_data = dyn_image.as_bytes().to_vec();
+    mut source: MipSource,
+) -> anyhow::Result<(u32, Vec<u8>)> {
+    source.first_level(dyn_image, filter_type);
+    let mut image_data = try_from_dynamic(dyn_image, format)?;
     let mut mip_level_count = 1;
     let mut width = dyn_image.width();
     let mut height = dyn_image.height();
@@ -185,18 +300,277 @@ pub fn generate_mips(
     {
         width /= 2;
         height /= 2;
-        *dyn_image = dyn_image.resize_exact(width, height, filter_type);
-        image_data.append(&mut dyn_image.as_bytes().to_vec());
+        source.downsample(dyn_image, width, height, filter_type);
+        image_data.append(&mut try_from_dynamic(dyn_image, format)?);
         mip_level_count += 1;
     }
 
-    (mip_level_count, image_data)
+    Ok((mip_level_count, image_data))
+}
+
+/// What a texture is used for, it decides how its mips are filtered.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum TextureRole {
+    /// Filtered channel by channel, in linear space for sRGB textures.
+    #[default]
+    Color,
+    /// Tangent space normals, averaged as vectors and renormalized, two channel formats only store
+    /// X and Y. `flip_y` is set for normal maps using the DirectX convention. It's undone when the
+    /// mips are encoded and flipping doesn't change how the normals average, so the mips come out
+    /// the same either way.
+    NormalMap { flip_y: bool },
+    /// Roughness in the G channel, filtered like a color. With `specular_antialiasing` the
+    /// roughness of the mips, and of the first level if `normal_map` is bigger, is raised by the
+    /// spread of the normals in `normal_map`.
+    MetallicRoughness { normal_map: Option<Handle<Image>> },
+}
+
+impl TextureRole {
+    fn name(&self) -> &'static str {
+        match self {
+            TextureRole::Color => "a color texture",
+            TextureRole::NormalMap { .. } => "a normal map",
+            TextureRole::MetallicRoughness { .. } => "a metallic roughness texture",
+        }
+    }
+}
+
+/// What the mips are filtered from, when the texture can't be filtered as it is.
+pub enum MipSource {
+    Direct,
+    /// Linear copy of an sRGB texture.
+    Linear(DynamicImage),
+    /// The normals of a normal map, in the OpenGL convention and encoded to 0..1 so they can be
+    /// resized. They're never renormalized so every level averages the normals of the first one.
+    /// `flip_y` puts the normals back in the convention of the texture when encoding them.
+    Normals {
+        normals: Rgba32FImage,
+        flip_y: bool,
+        two_channel: bool,
+    },
+    /// A metallic roughness texture and the normals of its material's normal map, like `Normals`.
+    Roughness {
+        roughness: DynamicImage,
+        normals: Rgba32FImage,
+    },
+}
+
+impl MipSource {
+    pub fn new(
+        dyn_image: &DynamicImage,
+        format: TextureFormat,
+        role: &TextureRole,
+        normal_map: Option<&Image>,
+        settings: &MipmapGeneratorSettings,
+    ) -> Self {
+        match role {
+            &TextureRole::NormalMap { flip_y } => MipSource::Normals {
+                normals: decode_normals(dyn_image, format, flip_y),
+                flip_y,
+                two_channel: dyn_image.color().channel_count() == 2,
+            },
+            // Roughness can only be written back to images with a G channel
+            TextureRole::MetallicRoughness { .. }
+                if settings.specular_antialiasing && dyn_image.color().channel_count() >= 3 =>
+            {
+                match normal_map.map(decode_normal_map).transpose() {
+                    Ok(Some(normals)) => MipSource::Roughness {
+                        roughness: dyn_image.clone(),
+                        normals,
+                    },
+                    Ok(None) => MipSource::Direct,
+                    Err(e) => {
+                        warn!("Skipping specular antialiasing: {e}");
+                        MipSource::Direct
+                    }
+                }
+            }
+            _ if settings.linear_srgb_filtering && format.describe().srgb => {
+                MipSource::Linear(srgb_to_linear(dyn_image))
+            }
+            _ => MipSource::Direct,
+        }
+    }
+
+    /// Adjusts the roughness of the first level when the normal map is bigger than the metallic
+    /// roughness texture, its normals already spread over a texel. Returns whether `dyn_image`
+    /// was replaced.
+    pub fn first_level(&mut self, dyn_image: &mut DynamicImage, filter_type: FilterType) -> bool {
+        let MipSource::Roughness { roughness, normals } = self else {
+            return false;
+        };
+        let (width, height) = (roughness.width(), roughness.height());
+        if normals.width() <= width && normals.height() <= height {
+            return false;
+        }
+        *normals = imageops::resize(normals, width, height, filter_type);
+        *dyn_image = adjust_roughness(roughness, normals);
+        true
+    }
+
+    /// Resizes the source and replaces `dyn_image` with the new level.
+    pub fn downsample(
+        &mut self,
+        dyn_image: &mut DynamicImage,
+        width: u32,
+        height: u32,
+        filter_type: FilterType,
+    ) {
+        match self {
+            MipSource::Direct => *dyn_image = dyn_image.resize_exact(width, height, filter_type),
+            MipSource::Linear(linear) => {
+                *linear = linear.resize_exact(width, height, filter_type);
+                *dyn_image = linear_to_srgb(linear);
+            }
+            MipSource::Normals {
+                normals,
+                flip_y,
+                two_channel,
+            } => {
+                *normals = imageops::resize(normals, width, height, filter_type);
+                *dyn_image = encode_normals(normals, *flip_y, *two_channel);
+            }
+            MipSource::Roughness { roughness, normals } => {
+                *roughness = roughness.resize_exact(width, height, filter_type);
+                *normals = imageops::resize(normals, width, height, filter_type);
+                *dyn_image = adjust_roughness(roughness, normals);
+            }
+        }
+    }
+}
+
+/// Decodes the normals of the first level of a normal map, see [`decode_normals`].
+fn decode_normal_map(normal_map: &Image) -> anyhow::Result<Rgba32FImage> {
+    check_image_compatible(normal_map)?;
+    let format = normal_map.texture_descriptor.format;
+    let size = normal_map.texture_descriptor.size;
+    let dyn_image = match bcn_format(format) {
+        Some(bcn) => decompress(&normal_map.data, size.width, size.height, bcn)?,
+        None => try_into_dynamic(extract_mip_level(normal_map, 1)?)?,
+    };
+    // Y only changes direction when flipped, not how much the normals spread
+    Ok(decode_normals(&dyn_image, format, false))
+}
+
+/// Decodes the normals to the OpenGL convention, encoded to 0..1 so they can be resized.
+/// Two channel formats get their Z reconstructed.
+fn decode_normals(dyn_image: &DynamicImage, format: TextureFormat, flip_y: bool) -> Rgba32FImage {
+    let two_channel = format.describe().components == 2;
+    // Two channel images converted to luma alpha keep Y in the alpha channel
+    let y_channel = if dyn_image.color().channel_count() == 2 {
+        3
+    } else {
+        1
+    };
+    let mut normals = dyn_image.to_rgba32f();
+    for pixel in normals.pixels_mut() {
+        let [r, _, b, a] = pixel.0;
+        let x = r * 2.0 - 1.0;
+        let mut y = pixel.0[y_channel] * 2.0 - 1.0;
+        if flip_y {
+            y = -y;
+        }
+        let z = if two_channel {
+            (1.0 - x * x - y * y).max(0.0).sqrt()
+        } else {
+            b * 2.0 - 1.0
+        };
+        // Quantized normals aren't quite unit length, which would read as spread in the mips
+        let normal = Vec3::new(x, y, z).try_normalize().unwrap_or(Vec3::Z) * 0.5 + 0.5;
+        let a = if y_channel == 3 { 1.0 } else { a };
+        pixel.0 = [normal.x, normal.y, normal.z, a];
+    }
+    normals
+}
+
+/// Raises the roughness in the G channel where the averaged `normals` are shorter than 1.
+fn adjust_roughness(roughness: &DynamicImage, normals: &Rgba32FImage) -> DynamicImage {
+    let mut adjusted = roughness.to_rgba32f();
+    for (pixel, normal) in adjusted.pixels_mut().zip(normals.pixels()) {
+        let [x, y, z, _] = normal.0;
+        let length = (Vec3::new(x, y, z) * 2.0 - 1.0).length();
+        pixel.0[1] = toksvig_roughness(pixel.0[1], length);
+    }
+    DynamicImage::ImageRgba32F(adjusted)
+}
+
+/// Widens the GGX lobe by the spread of the normals averaged to `normal_length`, taken as a
+/// von Mises-Fisher distribution like in Frequency Domain Normal Map Filtering (Han et al.).
+fn toksvig_roughness(roughness: f32, normal_length: f32) -> f32 {
+    if normal_length >= 1.0 {
+        return roughness;
+    }
+    let r = normal_length;
+    let kappa = (3.0 * r - r * r * r) / (1.0 - r * r);
+    let alpha = roughness * roughness;
+    // The slope variance of the lobe, alpha² / 2 on each axis, grows by 1 / kappa
+    let alpha = (alpha * alpha + 2.0 / kappa).sqrt();
+    alpha.sqrt().min(1.0)
+}
+
+/// Renormalizes the averaged normals and encodes them back to the layout of the texture.
+/// Two channel textures get a luma alpha image so the X and Y channels stay in place.
+fn encode_normals(normals: &Rgba32FImage, flip_y: bool, two_channel: bool) -> DynamicImage {
+    let mut encoded = normals.clone();
+    for pixel in encoded.pixels_mut() {
+        let [x, y, z, a] = pixel.0;
+        let normal = Vec3::new(x, y, z) * 2.0 - 1.0;
+        let mut normal = normal.try_normalize().unwrap_or(Vec3::Z);
+        if flip_y {
+            normal.y = -normal.y;
+        }
+        let normal = normal * 0.5 + 0.5;
+        pixel.0 = [normal.x, normal.y, normal.z, a];
+    }
+    if two_channel {
+        let luma_alpha = ImageBuffer::from_fn(encoded.width(), encoded.height(), |x, y| {
+            let [r, g, ..] = encoded.get_pixel(x, y).0;
+            LumaA([r, g].map(|value| (value * u16::MAX as f32).round() as u16))
+        });
+        DynamicImage::ImageLumaA16(luma_alpha)
+    } else {
+        DynamicImage::ImageRgba32F(encoded)
+    }
+}
+
+/// Decodes the color channels to linear RGBA32F, alpha is already linear.
+fn srgb_to_linear(dyn_image: &DynamicImage) -> DynamicImage {
+    let mut image = dyn_image.to_rgba32f();
+    for pixel in image.pixels_mut() {
+        for value in &mut pixel.0[..3] {
+            *value = if *value <= 0.04045 {
+                *value / 12.92
+            } else {
+                ((*value + 0.055) / 1.055).powf(2.4)
+            };
+        }
+    }
+    DynamicImage::ImageRgba32F(image)
+}
+
+/// Encodes the color channels back to sRGB, still as RGBA32F.
+fn linear_to_srgb(dyn_image: &DynamicImage) -> DynamicImage {
+    let mut image = dyn_image.to_rgba32f();
+    for pixel in image.pixels_mut() {
+        for value in &mut pixel.0[..3] {
+            *value = if *value <= 0.0031308 {
+                *value * 12.92
+            } else {
+                1.055 * value.powf(1.0 / 2.4) - 0.055
+            };
+        }
+    }
+    DynamicImage::ImageRgba32F(image)
 }
 
 /// Extract a specific individual mip level as a new image.
-#[allow(dead_code)]
 pub fn extract_mip_level(image: &Image, mip_level: u32) -> anyhow::Result<Image> {
     check_image_compatible(image)?;
+    if image.is_compressed() {
+        return Err(anyhow!(
+            "Extracting mip levels of compressed images not supported"
+        ));
+    }
 
     let descriptor = &image.texture_descriptor;
 
@@ -242,8 +616,11 @@ pub fn extract_mip_level(image: &Image, mip_level: u32) -> anyhow::Result<Image>
 }
 
 pub fn check_image_compatible(image: &Image) -> anyhow::Result<()> {
-    if image.is_compressed() {
-        return Err(anyhow!("Compressed images not supported"));
+    let format = image.texture_descriptor.format;
+    if image.is_compressed() && bcn_format(format).is_none() {
+        return Err(anyhow!(
+            "Compressed images are only supported in BC1 to BC7, got {format:?}"
+        ));
     }
     let descriptor = &image.texture_descriptor;
 
@@ -264,9 +641,31 @@ pub fn check_image_compatible(image: &Image) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// The BCn format of compressed textures, their mips are generated by decoding and re-encoding them.
+pub fn bcn_format(format: TextureFormat) -> Option<BcnFormat> {
+    Some(match format {
+        TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => BcnFormat::Bc1,
+        TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => BcnFormat::Bc2,
+        TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => BcnFormat::Bc3,
+        TextureFormat::Bc4RUnorm => BcnFormat::Bc4 { signed: false },
+        TextureFormat::Bc4RSnorm => BcnFormat::Bc4 { signed: true },
+        TextureFormat::Bc5RgUnorm => BcnFormat::Bc5 { signed: false },
+        TextureFormat::Bc5RgSnorm => BcnFormat::Bc5 { signed: true },
+        TextureFormat::Bc6hRgbUfloat => BcnFormat::Bc6h { signed: false },
+        TextureFormat::Bc6hRgbSfloat => BcnFormat::Bc6h { signed: true },
+        TextureFormat::Bc7RgbaUnorm | TextureFormat::Bc7RgbaUnormSrgb => BcnFormat::Bc7,
+        _ => return None,
+    })
+}
+
 // Implement the GetImages trait for any materials that need conversion
 pub trait GetImages {
     fn get_images(&self) -> Vec<&Option<Handle<Image>>>;
+
+    /// How the mips of `image` should be filtered.
+    fn texture_role(&self, _image: &Handle<Image>) -> TextureRole {
+        TextureRole::Color
+    }
 }
 
 impl GetImages for StandardMaterial {
@@ -279,41 +678,90 @@ impl GetImages for StandardMaterial {
             &self.occlusion_texture,
         ]
     }
+
+    fn texture_role(&self, image: &Handle<Image>) -> TextureRole {
+        let slots = self
+            .get_images()
+            .into_iter()
+            .filter(|slot| slot.as_ref() == Some(image))
+            .count();
+        let special = [&self.normal_map_texture, &self.metallic_roughness_texture]
+            .into_iter()
+            .filter(|slot| slot.as_ref() == Some(image))
+            .count();
+        if special > 0 && slots > 1 {
+            warn!(
+                "{image:?} has several roles in one material, its mips are filtered for the first \
+                 of normal map, metallic roughness and color"
+            );
+        }
+        if self.normal_map_texture.as_ref() == Some(image) {
+            TextureRole::NormalMap {
+                flip_y: self.flip_normal_map_y,
+            }
+        } else if self.metallic_roughness_texture.as_ref() == Some(image) {
+            TextureRole::MetallicRoughness {
+                normal_map: self.normal_map_texture.clone(),
+            }
+        } else {
+            TextureRole::Color
+        }
+    }
 }
 
+/// Converts the image data to a `DynamicImage`. BGRA is swizzled to RGBA, and the float formats
+/// are widened to RGBA32F with the missing channels set to 0 and alpha to 1.
 pub fn try_into_dynamic(image: Image) -> anyhow::Result<DynamicImage> {
+    let width = image.texture_descriptor.size.width;
+    let height = image.texture_descriptor.size.height;
     match image.texture_descriptor.format {
-        TextureFormat::R8Unorm => ImageBuffer::from_raw(
-            image.texture_descriptor.size.width,
-            image.texture_descriptor.size.height,
-            image.data,
-        )
-        .map(DynamicImage::ImageLuma8),
-        TextureFormat::Rg8Unorm => ImageBuffer::from_raw(
-            image.texture_descriptor.size.width,
-            image.texture_descriptor.size.height,
-            image.data,
-        )
-        .map(DynamicImage::ImageLumaA8),
-        TextureFormat::Rgba8UnormSrgb => ImageBuffer::from_raw(
-            image.texture_descriptor.size.width,
-            image.texture_descriptor.size.height,
-            image.data,
-        )
-        .map(DynamicImage::ImageRgba8),
-        TextureFormat::Rgba8Unorm => ImageBuffer::from_raw(
-            image.texture_descriptor.size.width,
-            image.texture_descriptor.size.height,
-            image.data,
-        )
-        .map(DynamicImage::ImageRgba8),
-        // Throw and error if conversion isn't supported
-        texture_format => {
-            return Err(anyhow!(
-                "Conversion into dynamic image not supported for {:?}.",
-                texture_format
-            ))
+        TextureFormat::R8Unorm => {
+            ImageBuffer::from_raw(width, height, image.data).map(DynamicImage::ImageLuma8)
+        }
+        TextureFormat::Rg8Unorm => {
+            ImageBuffer::from_raw(width, height, image.data).map(DynamicImage::ImageLumaA8)
         }
+        TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm => {
+            ImageBuffer::from_raw(width, height, image.data).map(DynamicImage::ImageRgba8)
+        }
+        TextureFormat::Bgra8UnormSrgb | TextureFormat::Bgra8Unorm => {
+            ImageBuffer::from_raw(width, height, swap_red_blue(image.data))
+                .map(DynamicImage::ImageRgba8)
+        }
+        TextureFormat::R16Unorm => ImageBuffer::from_raw(width, height, u16_from_le(&image.data))
+            .map(DynamicImage::ImageLuma16),
+        TextureFormat::Rg16Unorm => ImageBuffer::from_raw(width, height, u16_from_le(&image.data))
+            .map(DynamicImage::ImageLumaA16),
+        TextureFormat::Rgba16Unorm => {
+            ImageBuffer::from_raw(width, height, u16_from_le(&image.data))
+                .map(DynamicImage::ImageRgba16)
+        }
+        texture_format => match float_layout(texture_format) {
+            Some((channels, bytes)) => {
+                let rgba = image
+                    .data
+                    .chunks_exact(channels * bytes)
+                    .flat_map(|pixel| {
+                        let mut rgba = [0.0, 0.0, 0.0, 1.0];
+                        for (value, bytes) in rgba.iter_mut().zip(pixel.chunks_exact(bytes)) {
+                            *value = match bytes {
+                                [a, b] => f16::from_le_bytes([*a, *b]).to_f32(),
+                                bytes => f32::from_le_bytes(bytes.try_into().unwrap()),
+                            };
+                        }
+                        rgba
+                    })
+                    .collect();
+                ImageBuffer::from_raw(width, height, rgba).map(DynamicImage::ImageRgba32F)
+            }
+            // Throw and error if conversion isn't supported
+            None => {
+                return Err(anyhow!(
+                    "Conversion into dynamic image not supported for {:?}.",
+                    texture_format
+                ))
+            }
+        },
     }
     .ok_or_else(|| {
         anyhow!(
@@ -322,3 +770,234 @@ pub fn try_into_dynamic(image: Image) -> anyhow::Result<DynamicImage> {
         )
     })
 }
+
+/// Converts a `DynamicImage` made by [`try_into_dynamic`] back to image data in `format`.
+pub fn try_from_dynamic(
+    dyn_image: &DynamicImage,
+    format: TextureFormat,
+) -> anyhow::Result<Vec<u8>> {
+    Ok(match format {
+        TextureFormat::R8Unorm => dyn_image.to_luma8().into_raw(),
+        TextureFormat::Rg8Unorm => dyn_image.to_luma_alpha8().into_raw(),
+        TextureFormat::Rgba8UnormSrgb | TextureFormat::Rgba8Unorm => {
+            dyn_image.to_rgba8().into_raw()
+        }
+        TextureFormat::Bgra8UnormSrgb | TextureFormat::Bgra8Unorm => {
+            swap_red_blue(dyn_image.to_rgba8().into_raw())
+        }
+        TextureFormat::R16Unorm => u16_to_le(dyn_image.to_luma16().as_raw()),
+        TextureFormat::Rg16Unorm => u16_to_le(dyn_image.to_luma_alpha16().as_raw()),
+        TextureFormat::Rgba16Unorm => u16_to_le(dyn_image.to_rgba16().as_raw()),
+        texture_format => {
+            let Some((channels, bytes)) = float_layout(texture_format) else {
+                return Err(anyhow!(
+                    "Conversion from dynamic image not supported for {:?}.",
+                    texture_format
+                ));
+            };
+            dyn_image
+                .to_rgba32f()
+                .pixels()
+                .flat_map(|pixel| pixel.0[..channels].to_vec())
+                .flat_map(|value| match bytes {
+                    2 => f16::from_f32(value).to_le_bytes().to_vec(),
+                    _ => value.to_le_bytes().to_vec(),
+                })
+                .collect()
+        }
+    })
+}
+
+/// Number of channels and bytes per channel of the float formats.
+fn float_layout(format: TextureFormat) -> Option<(usize, usize)> {
+    Some(match format {
+        TextureFormat::R16Float => (1, 2),
+        TextureFormat::Rg16Float => (2, 2),
+        TextureFormat::Rgba16Float => (4, 2),
+        TextureFormat::R32Float => (1, 4),
+        TextureFormat::Rg32Float => (2, 4),
+        TextureFormat::Rgba32Float => (4, 4),
+        _ => return None,
+    })
+}
+
+fn swap_red_blue(mut data: Vec<u8>) -> Vec<u8> {
+    for pixel in data.chunks_exact_mut(4) {
+        pixel.swap(0, 2);
+    }
+    data
+}
+
+fn u16_from_le(data: &[u8]) -> Vec<u16> {
+    data.chunks_exact(2)
+        .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+        .collect()
+}
+
+fn u16_to_le(values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    const NORMAL: Vec3 = Vec3::new(0.36, -0.48, 0.8);
+
+    fn to_unorm8(normal: Vec3) -> [u8; 3] {
+        (normal * 0.5 + 0.5)
+            .to_array()
+            .map(|v| (v * 255.0).round() as u8)
+    }
+
+    fn normal_at(normals: &Rgba32FImage, x: u32, y: u32) -> Vec3 {
+        let [r, g, b, _] = normals.get_pixel(x, y).0;
+        Vec3::new(r, g, b) * 2.0 - 1.0
+    }
+
+    fn normal_map(normals: &[Vec3], width: u32) -> DynamicImage {
+        let height = normals.len() as u32 / width;
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+            let [r, g, b] = to_unorm8(normals[(y * width + x) as usize]);
+            Rgba([r, g, b, 255])
+        }))
+    }
+
+    fn normals_source(dyn_image: &DynamicImage, format: TextureFormat, flip_y: bool) -> MipSource {
+        let role = TextureRole::NormalMap { flip_y };
+        MipSource::new(dyn_image, format, &role, None, &default())
+    }
+
+    #[test]
+    fn rg8_normals_get_z_back() {
+        let [x, y, _] = to_unorm8(NORMAL);
+        let rg = DynamicImage::ImageLumaA8(ImageBuffer::from_pixel(4, 4, LumaA([x, y])));
+        let normals = decode_normals(&rg, TextureFormat::Rg8Unorm, false);
+        assert!(normal_at(&normals, 0, 0).distance(NORMAL) < 0.01);
+        assert_eq!(normals.get_pixel(0, 0).0[3], 1.0);
+
+        let encoded = encode_normals(&normals, false, true);
+        assert_eq!(encoded.color().channel_count(), 2);
+        let data = try_from_dynamic(&encoded, TextureFormat::Rg8Unorm).unwrap();
+        assert_eq!(data[..2], [x, y]);
+    }
+
+    #[test]
+    fn bc5_normals_get_z_back() {
+        let bc5 = BcnFormat::Bc5 { signed: false };
+        let data = compress_bcn(&normal_map(&[NORMAL; 16], 4), bc5);
+        let decoded = decompress(&data, 4, 4, bc5).unwrap();
+        let normals = decode_normals(&decoded, TextureFormat::Bc5RgUnorm, false);
+        assert!(normal_at(&normals, 1, 2).distance(NORMAL) < 0.01);
+
+        let encoded = encode_normals(&normals, false, false);
+        let round_trip = decompress(&compress_bcn(&encoded, bc5), 4, 4, bc5).unwrap();
+        assert_eq!(
+            round_trip.to_rgba8().get_pixel(1, 2).0[..2],
+            to_unorm8(NORMAL)[..2]
+        );
+    }
+
+    #[test]
+    fn quantized_normals_are_unit_length() {
+        let normals = decode_normals(&normal_map(&[NORMAL], 1), TextureFormat::Rgba8Unorm, false);
+        assert!((normal_at(&normals, 0, 0).length() - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn averaged_normals_are_renormalized() {
+        let tilted = [Vec3::new(0.6, 0.0, 0.8), Vec3::new(-0.6, 0.0, 0.8)];
+        let mut dyn_image = normal_map(&tilted, 2);
+        let mut source = normals_source(&dyn_image, TextureFormat::Rgba8Unorm, false);
+        source.downsample(&mut dyn_image, 1, 1, FilterType::Triangle);
+
+        // The source keeps the shortened average for the next levels
+        let MipSource::Normals { normals, .. } = &source else {
+            panic!("normal maps should be filtered as normals");
+        };
+        assert!((normal_at(normals, 0, 0).length() - 0.8).abs() < 0.01);
+        let mip = normal_at(&dyn_image.to_rgba32f(), 0, 0);
+        assert!(mip.distance(Vec3::Z) < 0.01);
+    }
+
+    #[test]
+    fn flip_y_gives_the_same_mips() {
+        let normals: Vec<_> = (0..16)
+            .map(|i| {
+                let angle = i as f32 * 0.4;
+                Vec3::new(angle.cos() * 0.5, angle.sin() * 0.5, 0.7).normalize()
+            })
+            .collect();
+        let mips = [false, true].map(|flip_y| {
+            let mut dyn_image = normal_map(&normals, 4);
+            let mut source = normals_source(&dyn_image, TextureFormat::Rgba8Unorm, flip_y);
+            source.downsample(&mut dyn_image, 2, 2, FilterType::Triangle);
+            dyn_image.to_rgba32f()
+        });
+        for (a, b) in mips[0].pixels().zip(mips[1].pixels()) {
+            for (a, b) in a.0.iter().zip(b.0) {
+                assert!((a - b).abs() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn toksvig_roughness_widens_with_the_spread() {
+        assert_eq!(toksvig_roughness(0.3, 1.0), 0.3);
+        let mut previous = 0.3;
+        for length in [0.99, 0.95, 0.9, 0.8, 0.6] {
+            let roughness = toksvig_roughness(0.3, length);
+            assert!(roughness > previous, "{roughness} at {length}");
+            previous = roughness;
+        }
+        assert_eq!(toksvig_roughness(0.3, 0.01), 1.0);
+        assert_eq!(toksvig_roughness(0.9, 0.5), 1.0);
+    }
+
+    fn roughness_with_normal_map(size: u32, normal_map_size: u32) -> Vec<u8> {
+        let extent = |size| Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 1,
+        };
+        // Alternating columns of tilted normals, they average to a shorter normal
+        let tilted = [Vec3::new(0.6, 0.0, 0.8), Vec3::new(-0.6, 0.0, 0.8)];
+        let normals: Vec<_> = (0..normal_map_size * normal_map_size)
+            .map(|i| tilted[(i % 2) as usize])
+            .collect();
+        let normal_map = Image::new(
+            extent(normal_map_size),
+            TextureDimension::D2,
+            normal_map(&normals, normal_map_size).into_bytes(),
+            TextureFormat::Rgba8Unorm,
+        );
+        let mut image = Image::new_fill(
+            extent(size),
+            TextureDimension::D2,
+            &[0, 77, 255, 255],
+            TextureFormat::Rgba8Unorm,
+        );
+        let role = TextureRole::MetallicRoughness { normal_map: None };
+        let settings = MipmapGeneratorSettings {
+            specular_antialiasing: true,
+            ..default()
+        };
+        generate_mips_texture(&mut image, &role, Some(&normal_map), &settings).unwrap();
+        image.data
+    }
+
+    #[test]
+    fn first_level_roughness_follows_a_bigger_normal_map() {
+        let data = roughness_with_normal_map(4, 8);
+        assert!(data[1] > 77);
+        // The normals are averaged per level, the mips get the same roughness
+        assert!(data[4 * 4 * 4 + 1].abs_diff(data[1]) <= 1);
+        let data = roughness_with_normal_map(4, 4);
+        assert_eq!(data[1], 77);
+        assert!(data[4 * 4 * 4 + 1] > 77);
+    }
+}
@@ -0,0 +1,175 @@
+//! Patterns matched against glTF names. Globs by default, `*` matches any run of characters and
+//! `?` a single one. Patterns written between slashes, like `/^floor_\d+$/`, are regexes.
+
+use std::fmt;
+
+use anyhow::anyhow;
+use regex::Regex;
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(try_from = "String")]
+pub struct NamePattern {
+    source: String,
+    regex: Option<Regex>,
+}
+
+impl Default for NamePattern {
+    fn default() -> Self {
+        Self {
+            source: "*".to_string(),
+            regex: None,
+        }
+    }
+}
+
+impl PartialEq for NamePattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl TryFrom<String> for NamePattern {
+    type Error = anyhow::Error;
+
+    fn try_from(source: String) -> Result<Self, Self::Error> {
+        let regex = match source
+            .strip_prefix('/')
+            .and_then(|pattern| pattern.strip_suffix('/'))
+        {
+            Some(pattern) => {
+                Some(Regex::new(pattern).map_err(|e| anyhow!("Invalid regex {source}: {e}"))?)
+            }
+            None => None,
+        };
+        Ok(Self { source, regex })
+    }
+}
+
+impl fmt::Display for NamePattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl NamePattern {
+    pub fn matches(&self, name: &str) -> bool {
+        match &self.regex {
+            Some(regex) => regex.is_match(name),
+            None => glob_matches(&self.source, name),
+        }
+    }
+}
+
+fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position of the last `*` and of the name when it was reached, to backtrack to
+    let mut star = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the `*` match one more character
+                Some((star_p, star_n)) => {
+                    star = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(source: &str) -> NamePattern {
+        NamePattern::try_from(source.to_string()).unwrap()
+    }
+
+    #[test]
+    fn star_matches_any_run() {
+        let floor = pattern("floor*");
+        assert!(floor.matches("floor"));
+        assert!(floor.matches("floor_01"));
+        assert!(!floor.matches("big_floor"));
+
+        assert!(pattern("*").matches(""));
+        assert!(pattern("*").matches("anything"));
+        assert!(pattern("**").matches("anything"));
+        assert!(pattern("*curtain*").matches("fabric_curtain_red"));
+        assert!(pattern("a*b*c").matches("aXbYbZc"));
+        // Needs to backtrack past the first `b`
+        assert!(pattern("*b?d").matches("abcbxd"));
+        assert!(!pattern("a*b*c").matches("aXbYbZ"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        let lion = pattern("lion_?");
+        assert!(lion.matches("lion_1"));
+        assert!(lion.matches("lion_é"));
+        assert!(!lion.matches("lion_"));
+        assert!(!lion.matches("lion_12"));
+        assert!(pattern("???").matches("abc"));
+        assert!(!pattern("???").matches("ab"));
+    }
+
+    #[test]
+    fn globs_match_the_whole_name() {
+        let floor = pattern("floor");
+        assert!(floor.matches("floor"));
+        assert!(!floor.matches("floor_01"));
+        assert!(!floor.matches("stone_floor"));
+        assert!(!floor.matches("Floor"));
+        assert!(!pattern("").matches("floor"));
+        assert!(pattern("").matches(""));
+    }
+
+    #[test]
+    fn regexes_between_slashes() {
+        let numbered = pattern(r"/^floor_\d+$/");
+        assert!(numbered.matches("floor_1"));
+        assert!(numbered.matches("floor_042"));
+        assert!(!numbered.matches("floor_"));
+        assert!(!numbered.matches("floor_1b"));
+
+        // Unanchored regexes match anywhere in the name
+        let curtain = pattern("/curtain/");
+        assert!(curtain.matches("fabric_curtain_red"));
+        assert!(!curtain.matches("Curtain"));
+        assert!(pattern("/(?i)curtain/").matches("Curtain"));
+
+        // Glob characters are regex syntax between slashes
+        assert!(pattern("/a*/").matches("b"));
+        // A single slash is a glob
+        assert!(pattern("/").matches("/"));
+        assert!(!pattern("/").matches(""));
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let error = NamePattern::try_from("/floor(/".to_string()).unwrap_err();
+        assert!(error.to_string().contains("/floor(/"), "{error}");
+    }
+
+    #[test]
+    fn default_matches_everything() {
+        let pattern = NamePattern::default();
+        assert!(pattern.matches(""));
+        assert!(pattern.matches("anything"));
+        assert_eq!(pattern.to_string(), "*");
+    }
+}
@@ -0,0 +1,356 @@
+//! Lists the glTF packages making up the scene, shared by the viewer and the converter.
+
+use anyhow::Context;
+use bevy::{prelude::*, render::render_resource::Face};
+use serde::Deserialize;
+use std::{
+    f32::consts::PI,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::name_pattern::NamePattern;
+
+#[derive(Resource, Deserialize, Clone, Debug)]
+pub struct SceneManifest {
+    pub packages: Vec<ScenePackage>,
+    /// File with `MaterialOverrides`, relative to the manifest.
+    #[serde(default)]
+    pub material_overrides: Option<PathBuf>,
+}
+
+/// Material rules applied to every package, after the rules of the package.
+#[derive(Resource, Deserialize, Clone, Debug, Default)]
+pub struct MaterialOverrides {
+    pub materials: Vec<MaterialRule>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ScenePackage {
+    pub name: String,
+    /// Relative to the asset root. If this is a folder, the first gltf file found in it is used.
+    pub gltf: PathBuf,
+    /// Optional packages are skipped when they haven't been downloaded.
+    #[serde(default)]
+    pub optional: bool,
+    /// Folder with the png textures to convert, relative to the asset root.
+    /// Defaults to the `textures` folder next to the gltf file.
+    #[serde(default)]
+    pub textures: Option<PathBuf>,
+    #[serde(default)]
+    pub transform: PackageTransform,
+    /// Fixes applied by `proc_scene` once the package is spawned.
+    #[serde(default)]
+    pub post_process: PostProcessRules,
+}
+
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct PostProcessRules {
+    /// The Sponza normal maps use the DirectX convention.
+    pub flip_normal_map_y: bool,
+    /// Despawns the lights exported with the scene.
+    pub strip_lights: bool,
+    /// Despawns the cameras exported with the scene.
+    pub strip_cameras: bool,
+    /// Applied in order to the materials with a matching name, after the replacements.
+    pub materials: Vec<MaterialRule>,
+    pub replace_materials: Vec<MaterialReplacement>,
+}
+
+/// Material tweaks, every field that isn't set is left as is.
+#[derive(Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct MaterialRule {
+    /// Matched against the glTF material name, matches every material by default.
+    pub name: NamePattern,
+    /// Only applies to materials that aren't opaque.
+    pub transparent_only: bool,
+    pub alpha_mode: Option<AlphaModeRule>,
+    /// Also disables backface culling.
+    pub double_sided: Option<bool>,
+    /// Linear RGBA, like the glTF factors.
+    pub base_color: Option<[f32; 4]>,
+    pub roughness: Option<f32>,
+    pub metallic: Option<f32>,
+    pub unlit: Option<bool>,
+    /// Linear RGB, applied before `emissive_scale`.
+    pub emissive: Option<[f32; 3]>,
+    /// Multiplies the emissive color.
+    pub emissive_scale: Option<f32>,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AlphaModeRule {
+    Opaque,
+    /// Alpha cutoff.
+    Mask(f32),
+    Blend,
+}
+
+impl From<AlphaModeRule> for AlphaMode {
+    fn from(rule: AlphaModeRule) -> Self {
+        match rule {
+            AlphaModeRule::Opaque => AlphaMode::Opaque,
+            AlphaModeRule::Mask(cutoff) => AlphaMode::Mask(cutoff),
+            AlphaModeRule::Blend => AlphaMode::Blend,
+        }
+    }
+}
+
+impl MaterialRule {
+    pub fn matches(&self, name: &str, opaque: bool) -> bool {
+        self.name.matches(name) && !(self.transparent_only && opaque)
+    }
+
+    pub fn apply(&self, name: &str, material: &mut StandardMaterial) {
+        if !self.matches(name, matches!(material.alpha_mode, AlphaMode::Opaque)) {
+            return;
+        }
+        if let Some(alpha_mode) = self.alpha_mode {
+            material.alpha_mode = alpha_mode.into();
+        }
+        if let Some(double_sided) = self.double_sided {
+            material.double_sided = double_sided;
+            material.cull_mode = if double_sided { None } else { Some(Face::Back) };
+        }
+        if let Some([r, g, b, a]) = self.base_color {
+            material.base_color = Color::rgba_linear(r, g, b, a);
+        }
+        if let Some(roughness) = self.roughness {
+            material.perceptual_roughness = roughness;
+        }
+        if let Some(metallic) = self.metallic {
+            material.metallic = metallic;
+        }
+        if let Some(unlit) = self.unlit {
+            material.unlit = unlit;
+        }
+        if let Some([r, g, b]) = self.emissive {
+            material.emissive = Color::rgb_linear(r, g, b);
+        }
+        if let Some(scale) = self.emissive_scale {
+            material.emissive *= scale;
+        }
+    }
+}
+
+/// Meshes using a material matching `name` use the material called `with` instead,
+/// both have to come from the same gltf file.
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct MaterialReplacement {
+    pub name: NamePattern,
+    pub with: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct PackageTransform {
+    pub translation: [f32; 3],
+    /// XYZ euler angles, in degrees.
+    pub rotation: [f32; 3],
+    pub scale: f32,
+}
+
+impl Default for PackageTransform {
+    fn default() -> Self {
+        Self {
+            translation: [0.0; 3],
+            rotation: [0.0; 3],
+            scale: 1.0,
+        }
+    }
+}
+
+impl From<&PackageTransform> for Transform {
+    fn from(transform: &PackageTransform) -> Self {
+        let [x, y, z] = transform.rotation.map(|degrees| degrees * PI / 180.0);
+        Transform {
+            translation: Vec3::from(transform.translation),
+            rotation: Quat::from_euler(EulerRot::XYZ, x, y, z),
+            scale: Vec3::splat(transform.scale),
+        }
+    }
+}
+
+impl SceneManifest {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scene manifest {}", path.display()))?;
+        let mut manifest: Self = ron::from_str(&contents)
+            .with_context(|| format!("Failed to parse scene manifest {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new("."));
+        manifest.material_overrides = manifest
+            .material_overrides
+            .map(|overrides| dir.join(overrides));
+        Ok(manifest)
+    }
+
+    pub fn load_material_overrides(&self) -> anyhow::Result<MaterialOverrides> {
+        let Some(path) = &self.material_overrides else {
+            return Ok(MaterialOverrides::default());
+        };
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read material overrides {}", path.display()))?;
+        ron::from_str(&contents)
+            .with_context(|| format!("Failed to parse material overrides {}", path.display()))
+    }
+
+    /// Finds the gltf file of the packages pointing at a folder and drops the optional packages that aren't downloaded.
+    pub fn resolve(mut self, asset_root: &Path) -> Self {
+        self.packages.retain_mut(|package| {
+            let path = package.gltf_path(asset_root);
+            if path.is_dir() {
+                match find_gltf(&path) {
+                    Some(gltf) => package.gltf = package.gltf.join(gltf),
+                    None if package.optional => {
+                        println!("Skipping {}, no gltf file found", package.name);
+                        return false;
+                    }
+                    // Left as is so the error points at the folder
+                    None => {}
+                }
+            } else if package.optional && !path.exists() {
+                println!("Skipping {}, not downloaded", package.name);
+                return false;
+            }
+            true
+        });
+        self
+    }
+}
+
+/// Returns the name of the first gltf file in the folder, sorted by name so the result is stable.
+fn find_gltf(dir: &Path) -> Option<PathBuf> {
+    let mut names: Vec<_> = fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .map(|entry| PathBuf::from(entry.file_name()))
+        .filter(|name| name.extension().is_some_and(|ext| ext == "gltf"))
+        .collect();
+    names.sort();
+    names.into_iter().next()
+}
+
+impl ScenePackage {
+    pub fn gltf_path(&self, asset_root: &Path) -> PathBuf {
+        asset_root.join(&self.gltf)
+    }
+
+    pub fn textures_dir(&self, asset_root: &Path) -> PathBuf {
+        match &self.textures {
+            Some(textures) => asset_root.join(textures),
+            None => self
+                .gltf_path(asset_root)
+                .parent()
+                .unwrap_or(asset_root)
+                .join("textures"),
+        }
+    }
+
+    /// Asset path of the gltf file, for the asset server.
+    pub fn gltf_asset_path(&self) -> String {
+        self.gltf.to_string_lossy().replace('\\', "/")
+    }
+
+    /// Asset path of the first scene in the gltf file.
+    pub fn scene_asset_path(&self) -> String {
+        format!("{}#Scene0", self.gltf_asset_path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(source: &str) -> MaterialRule {
+        ron::from_str(source).unwrap()
+    }
+
+    #[test]
+    fn rules_match_by_name() {
+        let every = rule("()");
+        assert!(every.matches("floor", true));
+        assert!(every.matches("glass", false));
+
+        let floor = rule(r#"(name: "floor*")"#);
+        assert!(floor.matches("floor_01", true));
+        assert!(floor.matches("floor_01", false));
+        assert!(!floor.matches("stone_floor", true));
+
+        let numbered = rule(r#"(name: "/^floor_\\d+$/")"#);
+        assert!(numbered.matches("floor_12", true));
+        assert!(!numbered.matches("floor_x", true));
+    }
+
+    #[test]
+    fn transparent_only_skips_opaque_materials() {
+        let foliage = rule(r#"(name: "leaf*", transparent_only: true)"#);
+        assert!(foliage.matches("leaf_ivy", false));
+        assert!(!foliage.matches("leaf_ivy", true));
+        assert!(!foliage.matches("bark", false));
+    }
+
+    #[test]
+    fn apply_overrides_the_set_fields() {
+        let curtain = rule(
+            r#"(
+                name: "/curtain/",
+                alpha_mode: Some(Mask(0.25)),
+                double_sided: Some(true),
+                base_color: Some((0.5, 0.25, 0.125, 1.0)),
+                roughness: Some(0.6),
+                metallic: Some(0.1),
+                unlit: Some(true),
+                emissive: Some((0.1, 0.2, 0.4)),
+                emissive_scale: Some(2.0),
+            )"#,
+        );
+        let mut material = StandardMaterial::default();
+        curtain.apply("fabric_curtain_red", &mut material);
+        assert_eq!(material.alpha_mode, AlphaMode::Mask(0.25));
+        assert!(material.double_sided);
+        assert_eq!(material.cull_mode, None);
+        assert_eq!(
+            material.base_color,
+            Color::rgba_linear(0.5, 0.25, 0.125, 1.0)
+        );
+        assert_eq!(material.perceptual_roughness, 0.6);
+        assert_eq!(material.metallic, 0.1);
+        assert!(material.unlit);
+        assert_eq!(material.emissive, Color::rgb_linear(0.2, 0.4, 0.8));
+
+        // Fields that aren't set are left alone
+        let mut material = StandardMaterial::default();
+        rule("(roughness: Some(0.3))").apply("floor", &mut material);
+        let default = StandardMaterial::default();
+        assert_eq!(material.perceptual_roughness, 0.3);
+        assert_eq!(material.metallic, default.metallic);
+        assert_eq!(material.base_color, default.base_color);
+        assert_eq!(material.alpha_mode, default.alpha_mode);
+        assert_eq!(material.cull_mode, default.cull_mode);
+    }
+
+    #[test]
+    fn apply_checks_the_alpha_mode() {
+        let foliage = rule("(transparent_only: true, alpha_mode: Some(Mask(0.5)))");
+
+        let mut opaque = StandardMaterial::default();
+        foliage.apply("leaf", &mut opaque);
+        assert_eq!(opaque.alpha_mode, AlphaMode::Opaque);
+
+        let mut blended = StandardMaterial {
+            alpha_mode: AlphaMode::Blend,
+            ..default()
+        };
+        foliage.apply("leaf", &mut blended);
+        assert_eq!(blended.alpha_mode, AlphaMode::Mask(0.5));
+    }
+
+    #[test]
+    fn shipped_scene_is_valid() {
+        let manifest = SceneManifest::load(Path::new("scenes/sponza.ron")).unwrap();
+        assert!(!manifest.packages.is_empty());
+        manifest.load_material_overrides().unwrap();
+    }
+}
@@ -0,0 +1,236 @@
+//! Lights and cameras that come with the gltf files, kept when the package doesn't strip them.
+//! The lights can be used instead of the lighting preset and the cameras become viewpoints.
+
+use std::{f32::consts::PI, str::FromStr};
+
+use anyhow::anyhow;
+use bevy::prelude::*;
+
+use crate::{
+    camera_controller::CameraController,
+    lighting::{GrifLight, LightingSystems},
+};
+
+/// Luminous efficacy used by Blender to convert watts to lumens.
+const LUMENS_PER_WATT: f32 = 683.0;
+
+pub struct SceneObjectsPlugin;
+
+impl Plugin for SceneObjectsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SceneObjects>()
+            .add_system(convert_gltf_lights)
+            .add_system(disable_gltf_cameras)
+            .add_system(toggle_gltf_lights)
+            .add_system(
+                show_lights
+                    .after(toggle_gltf_lights)
+                    .after(convert_gltf_lights)
+                    .after(LightingSystems),
+            )
+            .add_system(next_viewpoint);
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct SceneObjects {
+    /// Lights the scene with the gltf lights instead of the lighting preset.
+    pub gltf_lights: bool,
+    pub light_units: LightUnits,
+    pub key_toggle_lights: KeyCode,
+    pub key_next_viewpoint: KeyCode,
+}
+
+impl Default for SceneObjects {
+    fn default() -> Self {
+        Self {
+            gltf_lights: false,
+            light_units: LightUnits::Candela,
+            key_toggle_lights: KeyCode::G,
+            key_next_viewpoint: KeyCode::V,
+        }
+    }
+}
+
+impl SceneObjects {
+    pub fn print_controls(&self) {
+        println!(
+            "
+===============================
+======== Scene Objects ========
+===============================
+    {:?} - Toggle glTF Lights
+    {:?} - Next glTF Camera
+",
+            self.key_toggle_lights, self.key_next_viewpoint,
+        );
+    }
+}
+
+/// Unit of the light intensities in the gltf files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LightUnits {
+    /// What `KHR_lights_punctual` specifies: candela for point and spot lights and lux for
+    /// directional lights. The gltf loader already converts candela to lumens.
+    Candela,
+    /// Blender's unitless export writes the light power in watts, and W/m² for suns.
+    Watts,
+}
+
+impl LightUnits {
+    /// Converts the intensity set by the gltf loader to lumens for point and spot lights.
+    fn point_scale(self) -> f32 {
+        match self {
+            LightUnits::Candela => 1.0,
+            // The loader took the watts as candela and multiplied them by 4π
+            LightUnits::Watts => LUMENS_PER_WATT / (4.0 * PI),
+        }
+    }
+
+    /// Converts the illuminance set by the gltf loader to lux for directional lights.
+    fn directional_scale(self) -> f32 {
+        match self {
+            LightUnits::Candela => 1.0,
+            LightUnits::Watts => LUMENS_PER_WATT,
+        }
+    }
+}
+
+impl FromStr for LightUnits {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "candela" => Ok(LightUnits::Candela),
+            "watts" => Ok(LightUnits::Watts),
+            _ => Err(anyhow!(
+                "Unknown light units {s}, expected candela or watts"
+            )),
+        }
+    }
+}
+
+/// A light from a gltf file, marked by `proc_scene`.
+#[derive(Component)]
+pub struct GltfLight;
+
+/// A camera from a gltf file, marked by `proc_scene`. It doesn't render, the main camera can jump to it.
+#[derive(Component)]
+pub struct GltfCamera;
+
+type GltfLightQuery<'w, 's> = Query<
+    'w,
+    's,
+    AnyOf<(
+        &'static mut PointLight,
+        &'static mut SpotLight,
+        &'static mut DirectionalLight,
+    )>,
+    Added<GltfLight>,
+>;
+
+fn convert_gltf_lights(settings: Res<SceneObjects>, mut lights: GltfLightQuery) {
+    let units = settings.light_units;
+    for (point, spot, directional) in &mut lights {
+        if let Some(mut point) = point {
+            point.intensity *= units.point_scale();
+            // The loader uses the range as the radius, which turns every light into a huge sphere
+            point.radius = 0.0;
+        }
+        if let Some(mut spot) = spot {
+            spot.intensity *= units.point_scale();
+            spot.radius = 0.0;
+        }
+        if let Some(mut directional) = directional {
+            directional.illuminance *= units.directional_scale();
+        }
+    }
+}
+
+fn disable_gltf_cameras(mut cameras: Query<&mut Camera, Added<GltfCamera>>) {
+    for mut camera in &mut cameras {
+        camera.is_active = false;
+    }
+}
+
+fn toggle_gltf_lights(key_input: Res<Input<KeyCode>>, mut settings: ResMut<SceneObjects>) {
+    if key_input.just_pressed(settings.key_toggle_lights) {
+        settings.gltf_lights = !settings.gltf_lights;
+        println!(
+            "Using the {} lights",
+            if settings.gltf_lights {
+                "glTF"
+            } else {
+                "preset"
+            }
+        );
+    }
+}
+
+/// Runs every frame since the lighting preset can respawn its lights.
+fn show_lights(
+    settings: Res<SceneObjects>,
+    mut gltf_lights: Query<&mut Visibility, (With<GltfLight>, Without<GrifLight>)>,
+    mut rig_lights: Query<&mut Visibility, With<GrifLight>>,
+) {
+    let set = |visibility: &mut Mut<Visibility>, visible: bool| {
+        if visibility.is_visible != visible {
+            visibility.is_visible = visible;
+        }
+    };
+    for mut visibility in &mut gltf_lights {
+        set(&mut visibility, settings.gltf_lights);
+    }
+    for mut visibility in &mut rig_lights {
+        set(&mut visibility, !settings.gltf_lights);
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn next_viewpoint(
+    key_input: Res<Input<KeyCode>>,
+    settings: Res<SceneObjects>,
+    mut current: Local<Option<usize>>,
+    viewpoints: Query<(&GlobalTransform, &Projection, Option<&Name>), With<GltfCamera>>,
+    mut cameras: Query<
+        (&mut Transform, &mut Projection, &mut CameraController),
+        Without<GltfCamera>,
+    >,
+) {
+    if !key_input.just_pressed(settings.key_next_viewpoint) {
+        return;
+    }
+    let mut viewpoints: Vec<_> = viewpoints.iter().collect();
+    if viewpoints.is_empty() {
+        println!("No glTF cameras, pass --gltf-cameras to keep them");
+        return;
+    }
+    viewpoints.sort_by_key(|(.., name)| name.map(|name| name.as_str().to_string()));
+    let index = current.map_or(0, |index| (index + 1) % viewpoints.len());
+    *current = Some(index);
+    let (viewpoint_transform, viewpoint_projection, name) = viewpoints[index];
+    println!(
+        "Viewpoint {}",
+        name.map_or("unnamed camera", |name| name.as_str())
+    );
+
+    for (mut transform, mut projection, mut controller) in &mut cameras {
+        // The controller only takes over once the scene is ready
+        if !controller.enabled {
+            continue;
+        }
+        let viewpoint = viewpoint_transform.compute_transform();
+        transform.translation = viewpoint.translation;
+        transform.rotation = viewpoint.rotation;
+        if let (
+            Projection::Perspective(perspective),
+            Projection::Perspective(viewpoint_perspective),
+        ) = (&mut *projection, viewpoint_projection)
+        {
+            perspective.fov = viewpoint_perspective.fov;
+        }
+        // Picks up the new yaw and pitch
+        controller.initialized = false;
+        controller.velocity = Vec3::ZERO;
+    }
+}
@@ -0,0 +1,360 @@
+//! Renders a single frame without a window and writes it to a PNG or EXR file.
+//! The camera renders into an image that gets copied to a buffer and read back on the CPU.
+
+use std::{
+    num::NonZeroU32,
+    path::PathBuf,
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Mutex,
+    },
+};
+
+use anyhow::anyhow;
+use bevy::{
+    app::AppExit,
+    core_pipeline::tonemapping::Tonemapping,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        main_graph::node::CAMERA_DRIVER,
+        render_asset::RenderAssets,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext},
+        render_resource::{
+            Buffer, BufferDescriptor, BufferUsages, Extent3d, ImageCopyBuffer, ImageDataLayout,
+            MapMode, TextureDimension, TextureFormat, TextureUsages,
+        },
+        renderer::{RenderContext, RenderDevice},
+        RenderApp, RenderStage,
+    },
+};
+use image::{DynamicImage, ImageBuffer};
+
+use crate::{camera_controller::CameraController, loading::AppState};
+
+const SCREENSHOT_COPY: &str = "screenshot_copy";
+
+/// Frames rendered once everything is loaded, lets the mipmaps get uploaded and the lights settle.
+const WARMUP_FRAMES: u32 = 30;
+
+#[derive(Resource, Clone)]
+pub struct ScreenshotSettings {
+    pub output: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    /// Keeps the pose from `setup` when not set.
+    pub camera: Option<Transform>,
+    /// Vertical field of view in degrees, keeps the one from `setup` when not set.
+    pub fov: Option<f32>,
+    /// Gives up if the scene isn't loaded after this many seconds.
+    pub timeout: f32,
+}
+
+impl ScreenshotSettings {
+    /// EXR files get the linear HDR values, everything else is tonemapped.
+    fn hdr(&self) -> bool {
+        self.output
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("exr"))
+    }
+
+    fn texture_format(&self) -> TextureFormat {
+        if self.hdr() {
+            TextureFormat::Rgba16Float
+        } else {
+            TextureFormat::Rgba8UnormSrgb
+        }
+    }
+}
+
+/// Set once the app exits, the screenshot command fails if it isn't `Ok`.
+#[derive(Resource)]
+pub struct ScreenshotResult(pub anyhow::Result<()>);
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        let (sender, receiver) = channel();
+        app.insert_resource(ScreenshotReceiver(Mutex::new(receiver)))
+            .init_resource::<ScreenshotCapture>()
+            .add_plugin(ExtractResourcePlugin::<ScreenshotCapture>::default())
+            .add_startup_system(create_render_target)
+            .add_system(point_camera_at_target)
+            .add_system(capture_when_ready);
+
+        let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+        render_app
+            .insert_resource(ScreenshotSender(sender))
+            .init_resource::<ScreenshotBuffer>()
+            .add_system_to_stage(RenderStage::Prepare, prepare_screenshot_buffer)
+            .add_system_to_stage(RenderStage::Cleanup, read_screenshot_buffer);
+
+        let mut graph = render_app.world.resource_mut::<RenderGraph>();
+        graph.add_node(SCREENSHOT_COPY, ScreenshotCopyNode);
+        graph.add_node_edge(CAMERA_DRIVER, SCREENSHOT_COPY).unwrap();
+    }
+}
+
+/// The image the camera renders to, and whether it should be copied back this frame.
+#[derive(Resource, Clone, Default, ExtractResource)]
+struct ScreenshotCapture {
+    target: Handle<Image>,
+    capture: bool,
+}
+
+#[derive(Resource)]
+struct ScreenshotReceiver(Mutex<Receiver<Vec<u8>>>);
+
+#[derive(Resource)]
+struct ScreenshotSender(Sender<Vec<u8>>);
+
+/// Buffer the render target is copied to, only exists on the frame the screenshot is taken.
+#[derive(Resource, Default)]
+struct ScreenshotBuffer(Option<(Buffer, ReadbackLayout)>);
+
+#[derive(Clone, Copy)]
+struct ReadbackLayout {
+    width: u32,
+    height: u32,
+    bytes_per_pixel: u32,
+    /// Rows have to be aligned to 256 bytes when copying a texture to a buffer.
+    padded_bytes_per_row: u32,
+}
+
+impl ReadbackLayout {
+    fn new(width: u32, height: u32, format: TextureFormat) -> Self {
+        let bytes_per_pixel = format.describe().block_size as u32;
+        let padded_bytes_per_row = (width * bytes_per_pixel).div_ceil(256) * 256;
+        Self {
+            width,
+            height,
+            bytes_per_pixel,
+            padded_bytes_per_row,
+        }
+    }
+
+    fn unpad(&self, padded: &[u8]) -> Vec<u8> {
+        let row_bytes = (self.width * self.bytes_per_pixel) as usize;
+        padded
+            .chunks(self.padded_bytes_per_row as usize)
+            .take(self.height as usize)
+            .flat_map(|row| &row[..row_bytes])
+            .copied()
+            .collect()
+    }
+}
+
+fn create_render_target(
+    settings: Res<ScreenshotSettings>,
+    mut images: ResMut<Assets<Image>>,
+    mut capture: ResMut<ScreenshotCapture>,
+) {
+    let format = settings.texture_format();
+    let pixel_size = format.describe().block_size as usize;
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: settings.width,
+            height: settings.height,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &vec![0; pixel_size],
+        format,
+    );
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST
+        | TextureUsages::RENDER_ATTACHMENT;
+    capture.target = images.add(image);
+}
+
+/// Only the viewer camera renders to the image, the gltf cameras stay disabled.
+#[allow(clippy::type_complexity)]
+fn point_camera_at_target(
+    settings: Res<ScreenshotSettings>,
+    capture: Res<ScreenshotCapture>,
+    mut cameras: Query<
+        (
+            &mut Camera,
+            &mut Transform,
+            &mut Projection,
+            &mut Tonemapping,
+        ),
+        (Added<Camera>, With<CameraController>),
+    >,
+) {
+    for (mut camera, mut transform, mut projection, mut tonemapping) in &mut cameras {
+        camera.target = RenderTarget::Image(capture.target.clone());
+        if let Some(pose) = settings.camera {
+            *transform = pose;
+        }
+        if let (Some(fov), Projection::Perspective(perspective)) = (settings.fov, &mut *projection)
+        {
+            perspective.fov = fov.to_radians();
+        }
+        if settings.hdr() {
+            *tonemapping = Tonemapping::Disabled;
+        }
+    }
+}
+
+/// Counts the frames since the app is ready.
+#[derive(Default)]
+struct CaptureState {
+    ready_frames: u32,
+    requested: bool,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn capture_when_ready(
+    time: Res<Time>,
+    settings: Res<ScreenshotSettings>,
+    mut capture: ResMut<ScreenshotCapture>,
+    receiver: Res<ScreenshotReceiver>,
+    app_state: Res<State<AppState>>,
+    mut state: Local<CaptureState>,
+    mut commands: Commands,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    if state.requested {
+        capture.capture = false;
+        let Ok(data) = receiver.0.lock().unwrap().try_recv() else {
+            return;
+        };
+        let result = save_screenshot(&settings, data);
+        if result.is_ok() {
+            println!("Saved {}", settings.output.display());
+        }
+        commands.insert_resource(ScreenshotResult(result));
+        app_exit.send(AppExit);
+        return;
+    }
+
+    // The app goes back to post processing when assets get loaded late
+    state.ready_frames = match app_state.current() {
+        AppState::Ready => state.ready_frames + 1,
+        _ => 0,
+    };
+
+    if state.ready_frames >= WARMUP_FRAMES {
+        capture.capture = true;
+        state.requested = true;
+    } else if time.elapsed_seconds() > settings.timeout {
+        commands.insert_resource(ScreenshotResult(Err(anyhow!(
+            "The scene didn't finish loading after {} seconds",
+            settings.timeout
+        ))));
+        app_exit.send(AppExit);
+    }
+}
+
+fn save_screenshot(settings: &ScreenshotSettings, data: Vec<u8>) -> anyhow::Result<()> {
+    let (width, height) = (settings.width, settings.height);
+    let image = if settings.hdr() {
+        let pixels = data
+            .chunks_exact(2)
+            .map(|bytes| half::f16::from_le_bytes([bytes[0], bytes[1]]).to_f32())
+            .collect();
+        DynamicImage::ImageRgba32F(
+            ImageBuffer::from_raw(width, height, pixels)
+                .ok_or_else(|| anyhow!("Screenshot has the wrong size"))?,
+        )
+    } else {
+        DynamicImage::ImageRgba8(
+            ImageBuffer::from_raw(width, height, data)
+                .ok_or_else(|| anyhow!("Screenshot has the wrong size"))?,
+        )
+    };
+    image
+        .save(&settings.output)
+        .map_err(|e| anyhow!("Failed to write {}: {e}", settings.output.display()))
+}
+
+fn prepare_screenshot_buffer(
+    capture: Res<ScreenshotCapture>,
+    gpu_images: Res<RenderAssets<Image>>,
+    render_device: Res<RenderDevice>,
+    mut buffer: ResMut<ScreenshotBuffer>,
+) {
+    buffer.0 = None;
+    if !capture.capture {
+        return;
+    }
+    let Some(gpu_image) = gpu_images.get(&capture.target) else {
+        return;
+    };
+    let layout = ReadbackLayout::new(
+        gpu_image.size.x as u32,
+        gpu_image.size.y as u32,
+        gpu_image.texture_format,
+    );
+    let readback = render_device.create_buffer(&BufferDescriptor {
+        label: Some("screenshot_buffer"),
+        size: (layout.padded_bytes_per_row * layout.height) as u64,
+        usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    buffer.0 = Some((readback, layout));
+}
+
+struct ScreenshotCopyNode;
+
+impl Node for ScreenshotCopyNode {
+    fn run(
+        &self,
+        _graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let Some((buffer, layout)) = &world.resource::<ScreenshotBuffer>().0 else {
+            return Ok(());
+        };
+        let capture = world.resource::<ScreenshotCapture>();
+        let Some(gpu_image) = world.resource::<RenderAssets<Image>>().get(&capture.target) else {
+            return Ok(());
+        };
+        render_context.command_encoder.copy_texture_to_buffer(
+            gpu_image.texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(layout.padded_bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: layout.width,
+                height: layout.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Runs after the frame was submitted, waits for the copy and sends the pixels to the main world.
+fn read_screenshot_buffer(
+    buffer: Res<ScreenshotBuffer>,
+    render_device: Res<RenderDevice>,
+    sender: Res<ScreenshotSender>,
+) {
+    let Some((buffer, layout)) = &buffer.0 else {
+        return;
+    };
+    let slice = buffer.slice(..);
+    let (mapped_sender, mapped_receiver) = channel();
+    slice.map_async(MapMode::Read, move |result| {
+        let _ = mapped_sender.send(result);
+    });
+    render_device.poll(wgpu::Maintain::Wait);
+    if let Ok(Ok(())) = mapped_receiver.recv() {
+        let data = layout.unpad(&slice.get_mapped_range());
+        let _ = sender.0.send(data);
+    }
+    buffer.unmap();
+}
@@ -0,0 +1,368 @@
+//! Counts what the spawned scenes are made of and estimates how much GPU memory the textures take
+//! with and without mipmaps and block compression. Shown in an overlay or printed by `stats`.
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::anyhow;
+use bevy::{
+    app::AppExit, ecs::system::SystemParam, prelude::*, render::mesh::PrimitiveTopology,
+    utils::HashSet,
+};
+
+use crate::{hierarchy::descendants, loading::AppState, mipmap_generator::GetImages};
+
+#[derive(Default)]
+pub struct SceneStats {
+    /// Entities with a mesh.
+    pub mesh_instances: usize,
+    pub meshes: usize,
+    /// Summed over every instance, points and lines don't count.
+    pub triangles: u64,
+    pub vertices: u64,
+    /// Vertex and index buffers of the unique meshes.
+    pub mesh_bytes: u64,
+    pub materials: usize,
+    /// Count and loaded size of the textures, by format.
+    pub textures: BTreeMap<String, (usize, u64)>,
+    pub texture_bytes: TextureBytes,
+}
+
+/// Texture memory estimates, each texture is counted once.
+#[derive(Default)]
+pub struct TextureBytes {
+    /// What is actually loaded, with the mipmaps that were generated or came with the KTX2 files.
+    pub loaded: u64,
+    /// Every texture as RGBA8 without mipmaps, like the pngs get loaded.
+    pub rgba8: u64,
+    pub rgba8_mips: u64,
+    /// Every texture as 1 byte per pixel BC7 with mipmaps, what `convert` produces with its
+    /// default formats.
+    pub bc7_mips: u64,
+}
+
+#[derive(SystemParam)]
+pub struct SceneStatsParam<'w, 's> {
+    roots: Query<'w, 's, Entity, With<Handle<Scene>>>,
+    children: Query<'w, 's, &'static Children>,
+    mesh_entities: Query<
+        'w,
+        's,
+        (
+            &'static Handle<Mesh>,
+            Option<&'static Handle<StandardMaterial>>,
+        ),
+    >,
+    meshes: Res<'w, Assets<Mesh>>,
+    materials: Res<'w, Assets<StandardMaterial>>,
+    images: Res<'w, Assets<Image>>,
+}
+
+impl<'w, 's> SceneStatsParam<'w, 's> {
+    pub fn collect(&self) -> SceneStats {
+        let mut stats = SceneStats::default();
+        let mut meshes = HashSet::new();
+        let mut materials = HashSet::new();
+        for root in &self.roots {
+            let mesh_entities = descendants(root, &self.children).matching(&self.mesh_entities);
+            for (_, (mesh_h, material_h)) in mesh_entities {
+                stats.mesh_instances += 1;
+                if let Some(mesh) = self.meshes.get(mesh_h) {
+                    let vertices = mesh.count_vertices() as u64;
+                    let indices = mesh
+                        .indices()
+                        .map_or(vertices, |indices| indices.len() as u64);
+                    stats.vertices += vertices;
+                    stats.triangles += triangle_count(mesh.primitive_topology(), indices);
+                    if meshes.insert(mesh_h.id()) {
+                        let vertex_bytes: usize = mesh
+                            .attributes()
+                            .map(|(_, values)| values.get_bytes().len())
+                            .sum();
+                        let index_bytes = mesh.get_index_buffer_bytes().map_or(0, <[u8]>::len);
+                        stats.mesh_bytes += (vertex_bytes + index_bytes) as u64;
+                    }
+                }
+                if let Some(material_h) = material_h {
+                    materials.insert(material_h.clone());
+                }
+            }
+        }
+        stats.meshes = meshes.len();
+        stats.materials = materials.len();
+
+        let images: HashSet<_> = materials
+            .iter()
+            .filter_map(|material_h| self.materials.get(material_h))
+            .flat_map(|material| material.get_images().into_iter().flatten().cloned())
+            .collect();
+        for image in images.iter().filter_map(|image_h| self.images.get(image_h)) {
+            let size = image.texture_descriptor.size;
+            let (width, height) = (size.width as u64, size.height as u64);
+            let bytes = image.data.len() as u64;
+            let format = stats
+                .textures
+                .entry(format!("{:?}", image.texture_descriptor.format))
+                .or_default();
+            format.0 += 1;
+            format.1 += bytes;
+
+            let texture_bytes = &mut stats.texture_bytes;
+            texture_bytes.loaded += bytes;
+            texture_bytes.rgba8 += width * height * 4;
+            texture_bytes.rgba8_mips += mip_chain_bytes(width, height, 1, 4);
+            texture_bytes.bc7_mips += mip_chain_bytes(width, height, 4, 16);
+        }
+        stats
+    }
+}
+
+/// Number of triangles drawn from `indices` vertices, or the vertex count for unindexed meshes.
+fn triangle_count(topology: PrimitiveTopology, indices: u64) -> u64 {
+    match topology {
+        PrimitiveTopology::TriangleList => indices / 3,
+        PrimitiveTopology::TriangleStrip => indices.saturating_sub(2),
+        PrimitiveTopology::PointList
+        | PrimitiveTopology::LineList
+        | PrimitiveTopology::LineStrip => 0,
+    }
+}
+
+/// Size of a full mip chain down to 1x1, for `block_size` bytes per `block` x `block` pixels.
+fn mip_chain_bytes(mut width: u64, mut height: u64, block: u64, block_size: u64) -> u64 {
+    let mut bytes = 0;
+    loop {
+        bytes += width.div_ceil(block) * height.div_ceil(block) * block_size;
+        if width == 1 && height == 1 {
+            return bytes;
+        }
+        width = (width / 2).max(1);
+        height = (height / 2).max(1);
+    }
+}
+
+/// Formats a byte count in MiB.
+struct Mib(u64);
+
+impl fmt::Display for Mib {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.1} MiB", self.0 as f64 / (1024.0 * 1024.0))
+    }
+}
+
+impl fmt::Display for SceneStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Meshes: {} instances of {} meshes, {}",
+            self.mesh_instances,
+            self.meshes,
+            Mib(self.mesh_bytes)
+        )?;
+        writeln!(
+            f,
+            "Triangles: {}, vertices: {}",
+            self.triangles, self.vertices
+        )?;
+        writeln!(f, "Materials: {}", self.materials)?;
+        let textures: usize = self.textures.values().map(|(count, _)| count).sum();
+        writeln!(f, "Textures: {textures}")?;
+        for (format, (count, bytes)) in &self.textures {
+            writeln!(f, "  {format}: {count}, {}", Mib(*bytes))?;
+        }
+        let bytes = &self.texture_bytes;
+        writeln!(f, "Texture memory:")?;
+        writeln!(f, "  loaded: {}", Mib(bytes.loaded))?;
+        writeln!(f, "  RGBA8 without mips: {}", Mib(bytes.rgba8))?;
+        writeln!(f, "  RGBA8 with mips: {}", Mib(bytes.rgba8_mips))?;
+        write!(f, "  BC7 with mips: {}", Mib(bytes.bc7_mips))
+    }
+}
+
+/// Overlay showing the stats, toggled with a key.
+pub struct StatsOverlayPlugin;
+
+impl Plugin for StatsOverlayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<StatsOverlay>()
+            .add_startup_system(spawn_stats_overlay)
+            .add_system(update_stats_overlay);
+    }
+}
+
+#[derive(Resource, Clone)]
+pub struct StatsOverlay {
+    pub show: bool,
+    /// Relative to the asset root.
+    pub font: String,
+    pub key_toggle: KeyCode,
+}
+
+impl Default for StatsOverlay {
+    fn default() -> Self {
+        Self {
+            show: false,
+            font: "fonts/DejaVuSansMono.ttf".into(),
+            key_toggle: KeyCode::F1,
+        }
+    }
+}
+
+impl StatsOverlay {
+    pub fn print_controls(&self) {
+        println!(
+            "
+===============================
+========= Scene Stats =========
+===============================
+    {:?} - Toggle Stats
+",
+            self.key_toggle,
+        );
+    }
+}
+
+/// How often the overlay walks the scene again.
+const REFRESH_SECONDS: f32 = 1.0;
+
+#[derive(Component)]
+struct StatsPanel;
+
+#[derive(Component)]
+struct StatsText;
+
+fn spawn_stats_overlay(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    settings: Res<StatsOverlay>,
+) {
+    let style = TextStyle {
+        font: asset_server.load(&settings.font),
+        font_size: 16.0,
+        color: Color::WHITE,
+    };
+    commands
+        .spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(8.0),
+                        top: Val::Px(8.0),
+                        ..default()
+                    },
+                    padding: UiRect::all(Val::Px(8.0)),
+                    ..default()
+                },
+                background_color: Color::rgba(0.0, 0.0, 0.0, 0.7).into(),
+                visibility: Visibility {
+                    is_visible: settings.show,
+                },
+                ..default()
+            },
+            StatsPanel,
+        ))
+        .with_children(|parent| {
+            parent.spawn((TextBundle::from_section("", style), StatsText));
+        });
+}
+
+fn update_stats_overlay(
+    time: Res<Time>,
+    key_input: Res<Input<KeyCode>>,
+    mut settings: ResMut<StatsOverlay>,
+    mut since_refresh: Local<f32>,
+    stats: SceneStatsParam,
+    mut panels: Query<&mut Visibility, With<StatsPanel>>,
+    mut texts: Query<&mut Text, With<StatsText>>,
+) {
+    if key_input.just_pressed(settings.key_toggle) {
+        settings.show = !settings.show;
+        for mut visibility in &mut panels {
+            visibility.is_visible = settings.show;
+        }
+        // Refresh right away
+        *since_refresh = REFRESH_SECONDS;
+    } else {
+        *since_refresh += time.delta_seconds();
+    }
+    // Walking the whole scene every frame is too slow, and it only changes while loading
+    if !settings.show || *since_refresh < REFRESH_SECONDS {
+        return;
+    }
+    *since_refresh = 0.0;
+    let stats = stats.collect().to_string();
+    for mut text in &mut texts {
+        text.sections[0].value = stats.clone();
+    }
+}
+
+/// Prints the stats once the scene is ready and exits, for the `stats` command.
+pub struct StatsReportPlugin;
+
+impl Plugin for StatsReportPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(report_stats_when_ready);
+    }
+}
+
+/// Gives up if the scene isn't loaded after this many seconds.
+#[derive(Resource)]
+pub struct StatsTimeout(pub f32);
+
+/// Set once the app exits, the stats command fails if it isn't `Ok`.
+#[derive(Resource)]
+pub struct StatsResult(pub anyhow::Result<()>);
+
+fn report_stats_when_ready(
+    time: Res<Time>,
+    timeout: Res<StatsTimeout>,
+    app_state: Res<State<AppState>>,
+    stats: SceneStatsParam,
+    mut commands: Commands,
+    mut app_exit: EventWriter<AppExit>,
+) {
+    let result = if *app_state.current() == AppState::Ready {
+        println!("{}", stats.collect());
+        Ok(())
+    } else if time.elapsed_seconds() > timeout.0 {
+        Err(anyhow!(
+            "The scene didn't finish loading after {} seconds",
+            timeout.0
+        ))
+    } else {
+        return;
+    };
+    commands.insert_resource(StatsResult(result));
+    app_exit.send(AppExit);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mip_chain_sizes() {
+        // 4x4 + 2x2 + 1x1 pixels
+        assert_eq!(mip_chain_bytes(4, 4, 1, 4), (16 + 4 + 1) * 4);
+        assert_eq!(mip_chain_bytes(1, 1, 1, 4), 4);
+        // Non square levels stop shrinking at 1 pixel: 8x2, 4x1, 2x1, 1x1
+        assert_eq!(mip_chain_bytes(8, 2, 1, 1), 16 + 4 + 2 + 1);
+        // Every level takes at least one block: 8x8 is 4 blocks, then 1 block for 4x4, 2x2 and 1x1
+        assert_eq!(mip_chain_bytes(8, 8, 4, 16), (4 + 1 + 1 + 1) * 16);
+        // Partial blocks are rounded up, 5x5 is 2x2 blocks
+        assert_eq!(mip_chain_bytes(5, 5, 4, 16), (4 + 1 + 1) * 16);
+        // A full chain is just under a third larger than the top level
+        let top = 1024 * 1024 * 4;
+        assert_eq!(mip_chain_bytes(1024, 1024, 1, 4), (top * 4 - 4) / 3);
+    }
+
+    #[test]
+    fn triangles_by_topology() {
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleList, 9), 3);
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleStrip, 5), 3);
+        assert_eq!(triangle_count(PrimitiveTopology::TriangleStrip, 1), 0);
+        assert_eq!(triangle_count(PrimitiveTopology::LineList, 9), 0);
+        assert_eq!(triangle_count(PrimitiveTopology::LineStrip, 9), 0);
+        assert_eq!(triangle_count(PrimitiveTopology::PointList, 9), 0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Keeps track of which textures were converted, from what and how, so `--convert`
+//! only re-encodes the files that changed.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+pub const MANIFEST_FILE_NAME: &str = "ktx2_manifest.ron";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    /// blake3 hash of the source png.
+    pub source_hash: String,
+    /// Describes the encoder settings used, any change forces a re-encode.
+    pub settings: String,
+    /// File name of the output, relative to the textures folder.
+    pub output: String,
+    /// blake3 hash of the output, used to detect outputs that were modified or truncated.
+    pub output_hash: String,
+}
+
+/// One manifest per textures folder, keyed by the source file name.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct TextureManifest {
+    pub entries: BTreeMap<String, ManifestEntry>,
+}
+
+impl TextureManifest {
+    pub fn path(textures_dir: &Path) -> PathBuf {
+        textures_dir.join(MANIFEST_FILE_NAME)
+    }
+
+    /// Loads the manifest of a textures folder. A missing or unreadable manifest is treated as empty.
+    pub fn load(textures_dir: &Path) -> Self {
+        let path = Self::path(textures_dir);
+        match fs::read_to_string(&path) {
+            Ok(contents) => ron::from_str(&contents).unwrap_or_else(|e| {
+                eprintln!("Ignoring invalid manifest {}: {e}", path.display());
+                Self::default()
+            }),
+            Err(e) if e.kind() == ErrorKind::NotFound => Self::default(),
+            Err(e) => {
+                eprintln!("Ignoring unreadable manifest {}: {e}", path.display());
+                Self::default()
+            }
+        }
+    }
+
+    pub fn save(&self, textures_dir: &Path) -> anyhow::Result<()> {
+        let path = Self::path(textures_dir);
+        let contents = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())?;
+        fs::write(&path, contents).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Returns the entry if the output exists and was produced from the same source with the same settings.
+    pub fn up_to_date(
+        &self,
+        textures_dir: &Path,
+        source_name: &str,
+        source_hash: &str,
+        settings: &str,
+    ) -> Option<&ManifestEntry> {
+        let entry = self.entries.get(source_name)?;
+        if entry.source_hash != source_hash || entry.settings != settings {
+            return None;
+        }
+        let output_hash = hash_file(&textures_dir.join(&entry.output)).ok()?;
+        (output_hash == entry.output_hash).then_some(entry)
+    }
+}
+
+pub fn hash_file(path: &Path) -> anyhow::Result<String> {
+    let bytes = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    Ok(blake3::hash(&bytes).to_hex().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("sponza_{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn up_to_date() {
+        let dir = temp_dir("manifest_up_to_date");
+        fs::write(dir.join("a.ktx2"), b"converted").unwrap();
+        let mut manifest = TextureManifest::default();
+        manifest.entries.insert(
+            "a.png".to_string(),
+            ManifestEntry {
+                source_hash: "source".to_string(),
+                settings: "bc7".to_string(),
+                output: "a.ktx2".to_string(),
+                output_hash: hash_file(&dir.join("a.ktx2")).unwrap(),
+            },
+        );
+
+        assert!(manifest
+            .up_to_date(&dir, "a.png", "source", "bc7")
+            .is_some());
+        assert!(manifest
+            .up_to_date(&dir, "b.png", "source", "bc7")
+            .is_none());
+        assert!(manifest
+            .up_to_date(&dir, "a.png", "changed", "bc7")
+            .is_none());
+        assert!(manifest
+            .up_to_date(&dir, "a.png", "source", "bc5")
+            .is_none());
+
+        fs::write(dir.join("a.ktx2"), b"truncated").unwrap();
+        assert!(manifest
+            .up_to_date(&dir, "a.png", "source", "bc7")
+            .is_none());
+        fs::remove_file(dir.join("a.ktx2")).unwrap();
+        assert!(manifest
+            .up_to_date(&dir, "a.png", "source", "bc7")
+            .is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load() {
+        let dir = temp_dir("manifest_load");
+        assert!(TextureManifest::load(&dir).entries.is_empty());
+
+        let mut manifest = TextureManifest::default();
+        manifest.entries.insert(
+            "a.png".to_string(),
+            ManifestEntry {
+                source_hash: "source".to_string(),
+                settings: "bc7".to_string(),
+                output: "a.ktx2".to_string(),
+                output_hash: "output".to_string(),
+            },
+        );
+        manifest.save(&dir).unwrap();
+        assert_eq!(TextureManifest::load(&dir).entries, manifest.entries);
+
+        fs::write(TextureManifest::path(&dir), "not ron").unwrap();
+        assert!(TextureManifest::load(&dir).entries.is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,381 @@
+//! Moves the sun with the time of day, and dims, tints and moves the fake bounce lights with it.
+
+use bevy::prelude::*;
+
+use crate::lighting::{Bounce, LightingSystems, SunDriven};
+
+pub struct TimeOfDayPlugin;
+
+impl Plugin for TimeOfDayPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<TimeOfDay>()
+            .add_system(scrub_time_of_day)
+            .add_system(
+                apply_time_of_day
+                    .after(scrub_time_of_day)
+                    .after(LightingSystems),
+            );
+    }
+}
+
+/// Hours per second when scrubbing.
+const SCRUB_SPEED: f32 = 2.0;
+const SCRUB_SPEED_FAST: f32 = 8.0;
+
+#[derive(Resource, Clone, Debug)]
+pub struct TimeOfDay {
+    /// When disabled the lights keep the values from the lighting preset.
+    pub enabled: bool,
+    /// Local clock time, in hours.
+    pub hour: f32,
+    pub day_of_year: u32,
+    /// In degrees, north is positive.
+    pub latitude: f32,
+    /// In degrees, east is positive. Used with `utc_offset` to get the solar time from the clock time.
+    pub longitude: f32,
+    pub utc_offset: f32,
+    /// Direction of north in the scene, in degrees around Y from -Z.
+    pub north: f32,
+    pub key_toggle: KeyCode,
+    pub key_earlier: KeyCode,
+    pub key_later: KeyCode,
+    pub key_fast: KeyCode,
+}
+
+impl Default for TimeOfDay {
+    fn default() -> Self {
+        // Dubrovnik, where the real Sponza palace is, at the summer solstice
+        Self {
+            enabled: false,
+            hour: 12.0,
+            day_of_year: 172,
+            latitude: 42.64,
+            longitude: 18.11,
+            utc_offset: 2.0,
+            north: 0.0,
+            key_toggle: KeyCode::T,
+            key_earlier: KeyCode::LBracket,
+            key_later: KeyCode::RBracket,
+            key_fast: KeyCode::LShift,
+        }
+    }
+}
+
+impl TimeOfDay {
+    pub fn print_controls(&self) {
+        println!(
+            "
+===============================
+======== Time of Day ==========
+===============================
+    {:?} - Toggle
+    {:?}/{:?} - Earlier/Later
+    {:?} - Faster
+",
+            self.key_toggle, self.key_earlier, self.key_later, self.key_fast,
+        );
+    }
+
+    pub fn solar_hour(&self) -> f32 {
+        self.hour + self.longitude / 15.0 - self.utc_offset
+    }
+
+    /// Returns the elevation and the azimuth of the sun in radians, the azimuth goes clockwise from north.
+    pub fn sun_position(&self) -> (f32, f32) {
+        let day_angle = std::f32::consts::TAU / 365.0 * (self.day_of_year as f32 + 10.0);
+        let declination = (-23.44f32).to_radians() * day_angle.cos();
+        let hour_angle = (15.0 * (self.solar_hour() - 12.0)).to_radians();
+        let latitude = self.latitude.to_radians();
+
+        let sin_elevation = latitude.sin() * declination.sin()
+            + latitude.cos() * declination.cos() * hour_angle.cos();
+        let elevation = sin_elevation.clamp(-1.0, 1.0).asin();
+        let cos_azimuth = (declination.sin() - sin_elevation * latitude.sin())
+            / (elevation.cos() * latitude.cos()).max(1e-6);
+        let mut azimuth = cos_azimuth.clamp(-1.0, 1.0).acos();
+        if hour_angle.sin() > 0.0 {
+            azimuth = std::f32::consts::TAU - azimuth;
+        }
+        (elevation, azimuth)
+    }
+
+    /// Unit vector pointing towards the sun.
+    pub fn sun_direction(&self) -> Vec3 {
+        let (elevation, azimuth) = self.sun_position();
+        let azimuth = azimuth + self.north.to_radians();
+        Vec3::new(
+            azimuth.sin() * elevation.cos(),
+            elevation.sin(),
+            -azimuth.cos() * elevation.cos(),
+        )
+    }
+
+    /// How much of the preset intensity is left, 0 once the sun is down.
+    pub fn sun_strength(&self) -> f32 {
+        let (elevation, _) = self.sun_position();
+        elevation.sin().max(0.0).sqrt()
+    }
+
+    /// Multiplied with the preset colors, the light gets warmer as the sun gets lower.
+    pub fn sun_tint(&self) -> Vec3 {
+        let (elevation, _) = self.sun_position();
+        let t = (elevation / 20f32.to_radians()).clamp(0.0, 1.0);
+        Vec3::new(1.0, 0.5, 0.25).lerp(Vec3::ONE, t)
+    }
+
+    /// Moves a bounce light placed for the sun at `preset_to_sun` to follow the current sun.
+    pub fn bounce_transform(
+        &self,
+        base: &SunDriven,
+        bounce: &Bounce,
+        preset_to_sun: Vec3,
+    ) -> Transform {
+        bounce_transform(base, bounce, preset_to_sun, self.sun_direction())
+    }
+}
+
+fn bounce_transform(base: &SunDriven, bounce: &Bounce, from: Vec3, to: Vec3) -> Transform {
+    // Where the sun coming through the opening lands, relative to below the opening.
+    // Kept just above the horizon so it stays finite, the light is off by then anyway.
+    let patch = |to_sun: Vec3| {
+        let offset = -to_sun / to_sun.y.max(0.05) * bounce.opening_height;
+        Vec3::new(offset.x, 0.0, offset.z)
+    };
+    let offset = (patch(to) - patch(from)).clamp_length_max(bounce.max_offset);
+
+    let aim = |to_sun: Vec3| match bounce.reflected_off {
+        Some(normal) => {
+            let normal = Vec3::from(normal).normalize();
+            -to_sun + 2.0 * to_sun.dot(normal) * normal
+        }
+        None => -to_sun,
+    };
+    let rotation = Quat::from_rotation_arc(aim(from), aim(to));
+
+    Transform::from_translation(base.translation + offset).with_rotation(rotation * base.rotation)
+}
+
+fn scrub_time_of_day(
+    time: Res<Time>,
+    key_input: Res<Input<KeyCode>>,
+    mut time_of_day: ResMut<TimeOfDay>,
+) {
+    if key_input.just_pressed(time_of_day.key_toggle) {
+        time_of_day.enabled = !time_of_day.enabled;
+        println!(
+            "Time of day {}",
+            if time_of_day.enabled { "on" } else { "off" }
+        );
+    }
+
+    let mut direction = 0.0;
+    if key_input.pressed(time_of_day.key_earlier) {
+        direction -= 1.0;
+    }
+    if key_input.pressed(time_of_day.key_later) {
+        direction += 1.0;
+    }
+    if direction != 0.0 {
+        let speed = if key_input.pressed(time_of_day.key_fast) {
+            SCRUB_SPEED_FAST
+        } else {
+            SCRUB_SPEED
+        };
+        time_of_day.enabled = true;
+        time_of_day.hour =
+            (time_of_day.hour + direction * speed * time.delta_seconds()).rem_euclid(24.0);
+    }
+    if key_input.any_just_released([time_of_day.key_earlier, time_of_day.key_later]) {
+        let minutes = (time_of_day.hour * 60.0) as u32;
+        println!("Time of day {:02}:{:02}", minutes / 60, minutes % 60);
+    }
+}
+
+fn tinted(color: Color, tint: Vec3) -> Color {
+    Color::rgb(color.r() * tint.x, color.g() * tint.y, color.b() * tint.z)
+}
+
+type SunDrivenLights<'w, 's, L, F = ()> =
+    Query<'w, 's, (&'static SunDriven, &'static mut L, &'static mut Transform), F>;
+
+fn apply_time_of_day(
+    time_of_day: Res<TimeOfDay>,
+    changed: Query<(), Changed<SunDriven>>,
+    mut suns: SunDrivenLights<DirectionalLight>,
+    mut point_lights: SunDrivenLights<PointLight, Without<DirectionalLight>>,
+    mut spot_lights: SunDrivenLights<SpotLight, (Without<DirectionalLight>, Without<PointLight>)>,
+) {
+    // The preset values are restored when it gets disabled
+    if !time_of_day.is_changed() && changed.is_empty() {
+        return;
+    }
+
+    let (tint, strength) = if time_of_day.enabled {
+        (time_of_day.sun_tint(), time_of_day.sun_strength())
+    } else {
+        (Vec3::ONE, 1.0)
+    };
+
+    // The bounce lights are placed for the sun of the preset
+    let preset_to_sun = suns
+        .iter()
+        .next()
+        .map(|(base, _, _)| base.rotation * Vec3::Z);
+
+    for (base, mut light, mut transform) in &mut suns {
+        light.color = tinted(base.color, tint);
+        light.illuminance = base.intensity * strength;
+        transform.rotation = if time_of_day.enabled {
+            // Directional lights shine along their forward direction
+            let to_sun = time_of_day.sun_direction();
+            let up = if to_sun.y.abs() > 0.999 {
+                Vec3::Z
+            } else {
+                Vec3::Y
+            };
+            Transform::IDENTITY.looking_at(-to_sun, up).rotation
+        } else {
+            base.rotation
+        };
+    }
+    let bounce_transform = |base: &SunDriven| match (base.bounce, preset_to_sun) {
+        (Some(bounce), Some(preset_to_sun)) if time_of_day.enabled => {
+            time_of_day.bounce_transform(base, &bounce, preset_to_sun)
+        }
+        _ => Transform::from_translation(base.translation).with_rotation(base.rotation),
+    };
+    for (base, mut light, mut transform) in &mut point_lights {
+        light.color = tinted(base.color, tint);
+        light.intensity = base.intensity * strength;
+        if base.bounce.is_some() {
+            *transform = bounce_transform(base);
+        }
+    }
+    for (base, mut light, mut transform) in &mut spot_lights {
+        light.color = tinted(base.color, tint);
+        light.intensity = base.intensity * strength;
+        if base.bounce.is_some() {
+            *transform = bounce_transform(base);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// On the equator at the March equinox, with the clock on solar time.
+    fn equator_equinox(hour: f32) -> TimeOfDay {
+        TimeOfDay {
+            hour,
+            day_of_year: 80,
+            latitude: 0.0,
+            longitude: 0.0,
+            utc_offset: 0.0,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn noon_at_equator_on_equinox() {
+        let (elevation, _) = equator_equinox(12.0).sun_position();
+        assert!(elevation.to_degrees() > 88.0, "{}", elevation.to_degrees());
+        assert!(equator_equinox(12.0).sun_strength() > 0.99);
+    }
+
+    #[test]
+    fn sunrise_in_the_east() {
+        let (elevation, azimuth) = equator_equinox(6.0).sun_position();
+        assert!(
+            elevation.to_degrees().abs() < 2.0,
+            "{}",
+            elevation.to_degrees()
+        );
+        assert!(
+            (azimuth.to_degrees() - 90.0).abs() < 2.0,
+            "{}",
+            azimuth.to_degrees()
+        );
+        let (_, azimuth) = equator_equinox(18.0).sun_position();
+        assert!(
+            (azimuth.to_degrees() - 270.0).abs() < 2.0,
+            "{}",
+            azimuth.to_degrees()
+        );
+    }
+
+    #[test]
+    fn night() {
+        let time_of_day = TimeOfDay {
+            hour: 1.0,
+            ..default()
+        };
+        let (elevation, _) = time_of_day.sun_position();
+        assert!(elevation < 0.0);
+        assert_eq!(time_of_day.sun_strength(), 0.0);
+        assert!(time_of_day.sun_direction().y < 0.0);
+    }
+
+    fn bounce_light(reflected_off: Option<[f32; 3]>) -> (SunDriven, Bounce) {
+        let base = SunDriven {
+            color: Color::WHITE,
+            intensity: 1.0,
+            translation: Vec3::new(2.0, 0.0, -2.0),
+            rotation: Quat::from_rotation_x(0.3),
+            bounce: None,
+        };
+        let bounce = Bounce {
+            opening_height: 10.0,
+            reflected_off,
+            max_offset: 10.0,
+        };
+        (base, bounce)
+    }
+
+    #[test]
+    fn bounce_keeps_the_preset_for_the_preset_sun() {
+        let (base, bounce) = bounce_light(Some([0.0, 1.0, 0.0]));
+        let to_sun = Vec3::new(0.2, 1.0, 0.1).normalize();
+        let transform = bounce_transform(&base, &bounce, to_sun, to_sun);
+        assert!(transform.translation.abs_diff_eq(base.translation, 1e-5));
+        assert!(transform.rotation.abs_diff_eq(base.rotation, 1e-5));
+    }
+
+    #[test]
+    fn bounce_follows_the_sun() {
+        let (base, bounce) = bounce_light(Some([0.0, 1.0, 0.0]));
+        let overhead = Vec3::Y;
+        // 45 degrees to the east, the patch of sun moves west by the opening height
+        let east = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let transform = bounce_transform(&base, &bounce, overhead, east);
+        assert!(transform
+            .translation
+            .abs_diff_eq(base.translation - Vec3::new(10.0, 0.0, 0.0), 1e-4));
+        // Reflected off the floor, a light aimed straight up turns towards the west too
+        let up = Quat::from_rotation_arc(Vec3::NEG_Z, Vec3::Y);
+        let base = SunDriven {
+            rotation: up,
+            ..base
+        };
+        let aim = bounce_transform(&base, &bounce, overhead, east).forward();
+        assert!(aim.abs_diff_eq(Vec3::new(-1.0, 1.0, 0.0).normalize(), 1e-4));
+
+        // Along the sunlight, a light aimed straight down turns away from the sun
+        let (base, bounce) = bounce_light(None);
+        let base = SunDriven {
+            rotation: Quat::from_rotation_arc(Vec3::NEG_Z, Vec3::NEG_Y),
+            ..base
+        };
+        let aim = bounce_transform(&base, &bounce, overhead, east).forward();
+        assert!(aim.abs_diff_eq(-east, 1e-4));
+    }
+
+    #[test]
+    fn bounce_offset_is_clamped() {
+        let (base, bounce) = bounce_light(None);
+        let low_sun = Vec3::new(1.0, 0.01, 0.0).normalize();
+        let transform = bounce_transform(&base, &bounce, Vec3::Y, low_sun);
+        let offset = transform.translation - base.translation;
+        assert!((offset.length() - bounce.max_offset).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,125 @@
+//! Checks that the scene files are on disk before starting, so a missing download
+//! doesn't end up as a blank window and a wall of asset server errors.
+
+use anyhow::anyhow;
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    gltf_json::GltfDocument,
+    scene_manifest::{SceneManifest, ScenePackage},
+    texture_manifest::MANIFEST_FILE_NAME,
+};
+
+const DOWNLOAD_URL: &str =
+    "https://www.intel.com/content/www/us/en/developer/topic-technology/graphics-research/samples.html";
+
+/// Checks that every gltf file and the files it references exist, and lists the
+/// unreferenced files left in the textures folders.
+pub fn validate_assets(asset_root: &Path, scene: &SceneManifest) -> anyhow::Result<()> {
+    let mut missing = 0;
+    for package in &scene.packages {
+        let report = check_package(asset_root, package);
+        for path in &report.extra {
+            println!("{}: unused file {}", package.name, path.display());
+        }
+        if let Some(error) = &report.invalid {
+            missing += 1;
+            eprintln!("{}: {error:#}", package.name);
+        }
+        if report.missing.is_empty() {
+            continue;
+        }
+
+        missing += report.missing.len();
+        for path in &report.missing {
+            eprintln!("{}: missing {}", package.name, path.display());
+        }
+        if report.missing_ktx2_with_png {
+            eprintln!(
+                "Some KTX2 textures of {} are missing but their png is there, run `cargo run -- convert` or `cargo run -- revert`",
+                package.name
+            );
+        } else {
+            let folder = package.gltf_path(asset_root);
+            let folder = folder.parent().unwrap_or(asset_root);
+            eprintln!(
+                "Download \"{}\" from {DOWNLOAD_URL} and extract it into {}",
+                package.name,
+                folder.display()
+            );
+        }
+    }
+
+    if missing > 0 {
+        return Err(anyhow!("{missing} files are missing or invalid"));
+    }
+    println!("All files found");
+    Ok(())
+}
+
+#[derive(Default)]
+struct PackageReport {
+    missing: Vec<PathBuf>,
+    /// The gltf file exists but couldn't be read or parsed.
+    invalid: Option<anyhow::Error>,
+    extra: Vec<PathBuf>,
+    /// Every missing file is a KTX2 texture that can be regenerated from its png.
+    missing_ktx2_with_png: bool,
+}
+
+fn check_package(asset_root: &Path, package: &ScenePackage) -> PackageReport {
+    let mut report = PackageReport::default();
+    let path = package.gltf_path(asset_root);
+    let gltf = match GltfDocument::load(&path) {
+        Ok(gltf) => gltf,
+        Err(_) if !path.exists() => {
+            report.missing.push(path);
+            return report;
+        }
+        Err(e) => {
+            report.invalid = Some(e);
+            return report;
+        }
+    };
+
+    let mut referenced = HashSet::new();
+    let uris = gltf.image_uris().into_iter().chain(gltf.buffer_uris());
+    for (_, uri) in uris {
+        let path = gltf.dir().join(&uri);
+        if !path.is_file() {
+            report.missing.push(path.clone());
+        }
+        referenced.insert(path);
+    }
+    report.missing_ktx2_with_png = !report.missing.is_empty()
+        && report.missing.iter().all(|path| {
+            path.extension().is_some_and(|ext| ext == "ktx2")
+                && path.with_extension("png").is_file()
+        });
+
+    // The converter leaves a png and a ktx2 for each texture, only one of them is referenced
+    let referenced_stems: HashSet<_> = referenced
+        .iter()
+        .map(|path| path.with_extension(""))
+        .collect();
+    if let Ok(entries) = fs::read_dir(package.textures_dir(asset_root)) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            let is_manifest = path
+                .file_name()
+                .is_some_and(|name| name == MANIFEST_FILE_NAME);
+            if path.is_file()
+                && !is_manifest
+                && !referenced.contains(&path)
+                && !referenced_stems.contains(&path.with_extension(""))
+            {
+                report.extra.push(path);
+            }
+        }
+    }
+    report.extra.sort();
+    report
+}